@@ -0,0 +1,770 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use toml_edit::{Document, Item, Key};
+use unicode_width::UnicodeWidthStr;
+
+use crate::output::{self, Style};
+use crate::{history, matching, paths, sync, Config};
+
+static STATS_FILE: &str = "stats.toml";
+static LOCK_FILE: &str = ".bookmarks.lock";
+
+/// An entry in the bookmarks file. Either just a path, or a table with a path and tags.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Entry {
+    Path(String),
+    Table {
+        path: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+}
+
+impl Entry {
+    pub(crate) fn path(&self) -> &String {
+        match self {
+            Entry::Path(path) | Entry::Table { path, .. } => path,
+        }
+    }
+
+    pub(crate) fn tags(&self) -> &[String] {
+        match self {
+            Entry::Path(_) => &[],
+            Entry::Table { tags, .. } => tags,
+        }
+    }
+}
+
+/// A bookmark together with its usage statistics.
+pub(crate) struct Bookmark {
+    pub(crate) name: String,
+    pub(crate) path: String,
+    pub(crate) tags: Vec<String>,
+    pub(crate) added: Option<u64>,
+    pub(crate) used: Option<u64>,
+}
+
+impl Bookmark {
+    /// A bookmark without tags or statistics, like one that is about to be added.
+    pub(crate) fn new(name: String, path: String) -> Self {
+        Bookmark {
+            name,
+            path,
+            tags: vec![],
+            added: None,
+            used: None,
+        }
+    }
+
+    pub(crate) fn exists(&self) -> bool {
+        Path::new(&self.path).is_dir()
+    }
+}
+
+/// Usage statistics of a bookmark, stored in the `stats.toml` file next to the bookmarks file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Stats {
+    pub(crate) added: Option<u64>,
+    pub(crate) used: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum SortKey {
+    #[default]
+    Name,
+    Path,
+    Added,
+    Used,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "path" => Ok(SortKey::Path),
+            "added" => Ok(SortKey::Added),
+            "used" => Ok(SortKey::Used),
+            _ => Err(format!(
+                "unknown sort key `{s}`, expected one of: name, path, added, used"
+            )),
+        }
+    }
+}
+
+/// Which bookmarks to select from the store, and in what order.
+#[derive(Debug, Default)]
+pub(crate) struct Query {
+    /// Fuzzy pattern matched against names and paths
+    pub(crate) pattern: Option<String>,
+    /// Only bookmarks below this directory
+    pub(crate) under: Option<PathBuf>,
+    /// Only bookmarks whose directory does (`Some(true)`) or does not (`Some(false)`) exist
+    pub(crate) exists: Option<bool>,
+    /// Only bookmarks with this tag
+    pub(crate) tag: Option<String>,
+    /// Sort order. Defaults to the match score when a pattern is given, and to the name otherwise.
+    pub(crate) sort: Option<SortKey>,
+    pub(crate) reverse: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub(crate) struct Bookmarks {
+    pub(crate) file: PathBuf,
+    /// Prefix rewrites from stored paths to paths on this machine
+    pub(crate) path_maps: Vec<(String, String)>,
+}
+
+impl Bookmarks {
+    pub(crate) fn from(path: PathBuf) -> Bookmarks {
+        Bookmarks {
+            file: path,
+            path_maps: vec![],
+        }
+    }
+    pub(crate) fn from_config(config: &Config) -> Bookmarks {
+        Bookmarks {
+            file: config.get_bookmarks_file(),
+            path_maps: config.host_path_maps(),
+        }
+    }
+    pub(crate) fn get_map(&self) -> BTreeMap<String, String> {
+        match self.try_get_map() {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+        }
+    }
+    /// The names and paths of all bookmarks, with placeholders and references resolved.
+    pub(crate) fn try_get_map(&self) -> Result<BTreeMap<String, String>, String> {
+        Ok(self.resolve(&self.try_get_entries()?).0)
+    }
+
+    /// Resolve the paths of bookmarks, returning problems with references alongside.
+    fn resolve(&self, entries: &BTreeMap<String, Entry>) -> (BTreeMap<String, String>, Vec<String>) {
+        let stored = entries
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.path().clone()))
+            .collect();
+        paths::resolve(&stored, &self.path_maps)
+    }
+
+    /// Problems with references between bookmarks, like unknown bookmarks and cycles.
+    pub(crate) fn reference_problems(&self) -> Result<Vec<String>, String> {
+        Ok(self.resolve(&self.try_get_entries()?).1)
+    }
+    pub(crate) fn try_get_entries(&self) -> Result<BTreeMap<String, Entry>, String> {
+        let raw = self.read_raw()?;
+        toml::from_str(&raw).map_err(|e| {
+            format!(
+                "{e}\nERROR: Could not parse bookmarks file: `{}`",
+                self.file.display()
+            )
+        })
+    }
+    /// The contents of the bookmarks file. A missing file has no bookmarks.
+    fn read_raw(&self) -> Result<String, String> {
+        match fs::read_to_string(&self.file) {
+            Ok(raw) => Ok(raw),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(_) => Err(format!(
+                "ERROR: could not read bookmarks file: `{}`",
+                self.file.display()
+            )),
+        }
+    }
+
+    fn read_document(&self) -> Result<Document, String> {
+        self.read_raw()?.parse().map_err(|e| {
+            format!(
+                "{e}\nERROR: Could not parse bookmarks file: `{}`",
+                self.file.display()
+            )
+        })
+    }
+
+    fn write_document(&self, doc: &Document) -> Result<(), String> {
+        self.write_raw(&doc.to_string())
+    }
+
+    /// Change the bookmarks file while holding a lock on it, so that changes made at the same
+    /// time by another bmark process are not lost.
+    fn modify(&self, change: impl FnOnce(&mut Document) -> Result<(), String>) -> Result<(), String> {
+        let _lock = self.lock()?;
+        let mut doc = self.read_document()?;
+        change(&mut doc)?;
+        self.write_document(&doc)
+    }
+
+    /// Take an exclusive lock, which is released when the returned file is dropped.
+    fn lock(&self) -> Result<fs::File, String> {
+        let path = self.file.with_file_name(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("ERROR: Could not open lock file `{}`: {e}", path.display()))?;
+        file.lock()
+            .map_err(|e| format!("ERROR: Could not lock the bookmarks file: {e}"))?;
+        Ok(file)
+    }
+
+    /// Replace the contents of the bookmarks file, saving a snapshot of the old contents first.
+    pub(crate) fn write_raw(&self, contents: &str) -> Result<(), String> {
+        let old = self.read_raw()?;
+        if self.file.exists() && old != contents {
+            history::save(&self.file, &old)?;
+        }
+        self.replace(contents)
+    }
+
+    /// Replace the contents of the bookmarks file without taking a snapshot. When syncing is set
+    /// up, the change is committed.
+    pub(crate) fn replace(&self, contents: &str) -> Result<(), String> {
+        let old = self.read_raw()?;
+        write_atomic(&self.file, contents)?;
+
+        let changes = history::changes(&old, contents);
+        let message = match changes.is_empty() {
+            true => "bmark: edit bookmarks".to_string(),
+            false => format!("bmark: {}", changes.join(" ")),
+        };
+        if let Some(dir) = self.file.parent() {
+            if let Err(e) = sync::commit(dir, &message) {
+                eprintln!("{e}\nWARNING: Could not commit the change to the bookmarks.");
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a bookmark to the end of the bookmarks file.
+    pub(crate) fn insert(&self, name: &str, path: &str) -> Result<(), String> {
+        self.insert_many(&[(name.to_string(), path.to_string())])
+    }
+
+    /// Add bookmarks to the end of the bookmarks file in a single write. Nothing is added if any
+    /// of the names is already taken.
+    pub(crate) fn insert_many(&self, bookmarks: &[(String, String)]) -> Result<(), String> {
+        self.modify(|doc| {
+            for (name, path) in bookmarks {
+                if doc.contains_key(name) {
+                    return Err(format!(
+                        "A bookmark with the name '{name}' already exists."
+                    ));
+                }
+                doc.insert(name, toml_edit::value(path));
+            }
+            Ok(())
+        })
+    }
+
+    /// Add the bookmarks that `choose` picks given the current names and paths. The lock is held
+    /// from reading the bookmarks until the new ones are written, so that the choice cannot be
+    /// invalidated by another bmark process. Returns the added bookmarks.
+    pub(crate) fn insert_chosen(
+        &self,
+        choose: impl FnOnce(&BTreeMap<String, String>) -> Result<Vec<(String, String)>, String>,
+    ) -> Result<Vec<(String, String)>, String> {
+        let _lock = self.lock()?;
+        let new = choose(&self.try_get_map()?)?;
+        if !new.is_empty() {
+            let mut doc = self.read_document()?;
+            for (name, path) in &new {
+                doc.insert(name, toml_edit::value(path));
+            }
+            self.write_document(&doc)?;
+        }
+        Ok(new)
+    }
+
+    /// Add bookmarks, or point the ones that already exist to a new path, in a single write.
+    /// Existing bookmarks keep their tags unless new tags are given.
+    pub(crate) fn set_many(&self, bookmarks: &[(String, Entry)]) -> Result<(), String> {
+        self.modify(|doc| {
+            for (name, entry) in bookmarks {
+                let tags = match entry {
+                    Entry::Table { tags, .. } if !tags.is_empty() => Some(tags.iter().collect::<toml_edit::Array>()),
+                    _ => None,
+                };
+                match doc.get_mut(name).and_then(|item| item.as_table_like_mut()) {
+                    Some(table) => {
+                        table.insert("path", toml_edit::value(entry.path()));
+                        if let Some(tags) = tags {
+                            table.insert("tags", toml_edit::value(tags));
+                        }
+                    }
+                    None => match tags {
+                        Some(tags) => {
+                            let mut table = toml_edit::InlineTable::new();
+                            table.insert("path", entry.path().into());
+                            table.insert("tags", tags.into());
+                            doc.insert(name, toml_edit::value(table));
+                        }
+                        None => doc[name.as_str()] = toml_edit::value(entry.path()),
+                    },
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove bookmarks, leaving the rest of the bookmarks file untouched. Nothing is removed if
+    /// any of the bookmarks does not exist.
+    /// Remove bookmarks. Nothing is removed if another bookmark still starts from one of them
+    /// with `${name}`.
+    pub(crate) fn remove(&self, names: &[String]) -> Result<(), String> {
+        self.modify(|doc| {
+            for name in names {
+                if doc.remove(name).is_none() {
+                    return Err(not_found(name, doc.iter().map(|(k, _)| k)));
+                }
+            }
+            for (other, item) in doc.iter() {
+                let refs = stored_path(item).map(paths::references).unwrap_or_default();
+                if let Some(r) = refs.iter().find(|r| names.iter().any(|n| n == *r)) {
+                    return Err(format!(
+                        "ERROR: Bookmark `{other}` starts from `{r}` with `${{{r}}}`, change or remove it first."
+                    ));
+                }
+            }
+            Ok(())
+        })?;
+        self.forget(names);
+        Ok(())
+    }
+
+    /// Rename a bookmark in place, keeping its position, comments and tags.
+    pub(crate) fn rename(&self, old: &str, new: &str) -> Result<(), String> {
+        self.modify(|doc| {
+            if !doc.contains_key(old) {
+                return Err(format!("ERROR: could not find bookmark `{}`.", old));
+            }
+            if doc.contains_key(new) {
+                return Err(format!(
+                    "A bookmark with the name '{new}' already exists."
+                ));
+            }
+            // Bookmarks that start from the renamed one follow it
+            let (from, to) = (format!("${{{old}}}"), format!("${{{new}}}"));
+            for (_, item) in doc.iter_mut() {
+                let value = match item.as_table_like_mut() {
+                    Some(table) => table.get_mut("path").and_then(Item::as_value_mut),
+                    None => item.as_value_mut(),
+                };
+                if let Some(value) = value.filter(|v| v.as_str().is_some_and(|p| p.contains(&from))) {
+                    let path = value.as_str().unwrap_or_default().replace(&from, &to);
+                    let decor = value.decor().clone();
+                    *value = path.into();
+                    *value.decor_mut() = decor;
+                }
+            }
+
+            let table = doc.as_table_mut();
+            let keys: Vec<String> = table.iter().map(|(k, _)| k.to_string()).collect();
+            for k in keys {
+                let (mut key, item): (Key, Item) =
+                    table.remove_entry(&k).expect("key was just listed");
+                if k == old {
+                    key = Key::new(new).with_decor(key.decor().clone());
+                }
+                table.insert_formatted(&key, item);
+            }
+            Ok(())
+        })?;
+
+        let mut stats = self.get_stats();
+        if let Some(s) = stats.remove(old) {
+            stats.insert(new.to_string(), s);
+            self.write_stats(&stats);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn readable_with_paths(&self, sep: &String) -> String {
+        Self::table(&self.entries(), sep, &Style::default())
+    }
+
+    /// Format bookmarks as lines of names and paths, with the paths aligned in a column.
+    pub(crate) fn table(entries: &[Bookmark], sep: &String, style: &Style) -> String {
+        let home = match style.tilde {
+            true => env::var("HOME").ok().filter(|h| !h.is_empty()),
+            false => None,
+        };
+        let max_len = entries.iter().map(|b| b.name.width()).max().unwrap_or(0);
+
+        let mut res = String::new();
+        for b in entries {
+            let padding = " ".repeat(max_len - b.name.width());
+            let mut path = match &home {
+                Some(home) => output::tilde(&b.path, home),
+                None => b.path.clone(),
+            };
+            let mut tags = String::new();
+            if style.tags {
+                for t in &b.tags {
+                    tags += format!(" #{t}").as_str();
+                }
+            }
+            if let Some(width) = style.width {
+                let available = width.saturating_sub(max_len + sep.width() + tags.width());
+                path = output::ellipsise(&path, available);
+            }
+            if style.color {
+                if !b.exists() {
+                    path = output::red(&path);
+                }
+                if !tags.is_empty() {
+                    tags = output::dim(&tags);
+                }
+            }
+            res += format!("{}{}{}{}{}\n", b.name, padding, sep, path, tags).as_str();
+        }
+        res
+    }
+
+    pub(crate) fn readable(&self) -> String {
+        self.entries().into_iter().map(|b| b.name + "\n").collect()
+    }
+
+    fn stats_file(&self) -> PathBuf {
+        self.file.with_file_name(STATS_FILE)
+    }
+
+    pub(crate) fn get_stats(&self) -> BTreeMap<String, Stats> {
+        fs::read_to_string(self.stats_file())
+            .ok()
+            .and_then(|raw| toml::from_str(&raw).ok())
+            .unwrap_or_default()
+    }
+
+    /// Update the usage statistics of a bookmark. Statistics are not essential, so failing to
+    /// write them only results in a warning.
+    fn update_stats(&self, name: &str, update: impl FnOnce(&mut Stats)) {
+        let mut stats = self.get_stats();
+        update(stats.entry(name.to_string()).or_default());
+        self.write_stats(&stats);
+    }
+
+    fn write_stats(&self, stats: &BTreeMap<String, Stats>) {
+        let written = toml::to_string(stats)
+            .map_err(|e| e.to_string())
+            .and_then(|s| fs::write(self.stats_file(), s).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("WARNING: Could not write bookmark statistics: {e}");
+        }
+    }
+
+    /// Record that a bookmark was just added.
+    pub(crate) fn mark_added(&self, names: &[String]) {
+        let mut stats = self.get_stats();
+        for name in names {
+            stats.insert(name.clone(), Stats { added: Some(now()), used: None });
+        }
+        self.write_stats(&stats);
+    }
+
+    /// Record that a bookmark was just used.
+    pub(crate) fn mark_used(&self, name: &str) {
+        self.update_stats(name, |s| s.used = Some(now()));
+    }
+
+    /// Forget the statistics of removed bookmarks.
+    pub(crate) fn forget(&self, names: &[String]) {
+        let mut stats = self.get_stats();
+        let before = stats.len();
+        stats.retain(|name, _| !names.contains(name));
+        if stats.len() != before {
+            self.write_stats(&stats);
+        }
+    }
+
+    /// All bookmarks with their statistics, sorted by name. Bookmarks with a glob pattern are
+    /// replaced by the directories matching it, named `name/<match>`.
+    pub(crate) fn entries(&self) -> Vec<Bookmark> {
+        let mut stats = self.get_stats();
+        let entries = match self.try_get_entries() {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+        };
+        let mut resolved = self.resolve(&entries).0;
+        let mut bookmarks = vec![];
+        for (name, entry) in entries {
+            let s = stats.remove(&name).unwrap_or_default();
+            let tags = match entry {
+                Entry::Path(_) => vec![],
+                Entry::Table { tags, .. } => tags,
+            };
+            let path = resolved.remove(&name).unwrap_or_default();
+            if !paths::is_glob(&path) {
+                bookmarks.push(Bookmark {
+                    name,
+                    path,
+                    tags,
+                    added: s.added,
+                    used: s.used,
+                });
+                continue;
+            }
+            for (matched, dir) in paths::glob(&path) {
+                let name = format!("{name}/{matched}");
+                bookmarks.push(Bookmark {
+                    used: stats.get(&name).and_then(|s| s.used),
+                    name,
+                    path: dir.display().to_string(),
+                    tags: tags.clone(),
+                    added: s.added,
+                });
+            }
+        }
+        bookmarks
+    }
+
+    /// Select and sort bookmarks.
+    pub(crate) fn query(&self, query: &Query) -> Vec<Bookmark> {
+        let under = query
+            .under
+            .as_ref()
+            .map(|d| d.canonicalize().unwrap_or(d.clone()));
+
+        let mut found: Vec<(i64, Bookmark)> = self
+            .entries()
+            .into_iter()
+            .filter(|b| query.exists.is_none_or(|e| b.exists() == e))
+            .filter(|b| query.tag.as_ref().is_none_or(|t| b.tags.contains(t)))
+            .filter(|b| match &under {
+                Some(dir) => {
+                    let path = PathBuf::from(&b.path);
+                    path.canonicalize().unwrap_or(path).starts_with(dir)
+                }
+                None => true,
+            })
+            .filter_map(|b| match &query.pattern {
+                Some(p) => {
+                    let score = matching::fuzzy_score(p, &b.name)
+                        .max(matching::fuzzy_score(p, &b.path))?;
+                    Some((score, b))
+                }
+                None => Some((0, b)),
+            })
+            .collect();
+
+        match query.sort {
+            None if query.pattern.is_some() => found.sort_by_key(|f| Reverse(f.0)),
+            None | Some(SortKey::Name) => {}
+            Some(SortKey::Path) => found.sort_by(|a, b| a.1.path.cmp(&b.1.path)),
+            // Most recent first
+            Some(SortKey::Added) => found.sort_by_key(|f| Reverse(f.1.added)),
+            Some(SortKey::Used) => found.sort_by_key(|f| Reverse(f.1.used)),
+        }
+        if query.reverse {
+            found.reverse();
+        }
+        found.into_iter().map(|(_, b)| b).collect()
+    }
+
+    /// Find a bookmark by name. Tries an exact match first, then a unique prefix and finally a
+    /// unique fuzzy match.
+    pub(crate) fn lookup(&self, query: &str) -> Result<(String, String), String> {
+        let map = self.get_map();
+
+        if let Some((k, v)) = map.get_key_value(query) {
+            return Ok((k.clone(), v.clone()));
+        }
+
+        let prefixed: Vec<&String> = map.keys().filter(|k| k.starts_with(query)).collect();
+        if prefixed.len() == 1 {
+            let k = prefixed[0];
+            return Ok((k.clone(), map[k].clone()));
+        }
+        if prefixed.len() > 1 {
+            return Err(Self::ambiguous(query, prefixed));
+        }
+
+        let mut fuzzy: Vec<(i64, &String)> = map
+            .keys()
+            .filter_map(|k| matching::fuzzy_score(query, k).map(|s| (s, k)))
+            .collect();
+        fuzzy.sort_by_key(|f| Reverse(f.0));
+        match fuzzy.len() {
+            0 => Err(format!("ERROR: could not find bookmark `{query}`.")),
+            1 => {
+                let k = fuzzy[0].1;
+                Ok((k.clone(), map[k].clone()))
+            }
+            _ => Err(Self::ambiguous(query, fuzzy.into_iter().map(|(_, k)| k).collect())),
+        }
+    }
+
+    /// Like [`Bookmarks::lookup`], but also accepts `name/rest` for a directory below a bookmark
+    /// or a directory matched by a glob bookmark.
+    pub(crate) fn lookup_path(&self, query: &str) -> Result<(String, String), String> {
+        match (self.lookup(query), query.split_once('/')) {
+            (Err(_), Some((name, rest))) if !name.is_empty() => {
+                if let Some(b) = self.entries().into_iter().find(|b| b.name == query) {
+                    return Ok((b.name, b.path));
+                }
+                let (name, path) = self.lookup(name)?;
+                Ok((name, format!("{}/{rest}", path.trim_end_matches('/'))))
+            }
+            (found, _) => found,
+        }
+    }
+
+    fn ambiguous(query: &str, candidates: Vec<&String>) -> String {
+        let mut msg = format!("ERROR: `{query}` is ambiguous, candidates are:");
+        for c in candidates {
+            msg += format!("\n    {c}").as_str();
+        }
+        msg
+    }
+
+    /// All bookmarks whose directory contains `dir`, innermost bookmark first.
+    pub(crate) fn containing(&self, dir: &Path) -> Vec<(String, String)> {
+        let dir = dir.canonicalize().unwrap_or(dir.to_path_buf());
+        let mut found: Vec<(usize, String, String)> = vec![];
+        for (k, v) in self.get_map() {
+            let path = PathBuf::from(&v);
+            let path = path.canonicalize().unwrap_or(path);
+            if dir.starts_with(&path) {
+                found.push((path.components().count(), k, v));
+            }
+        }
+        found.sort_by_key(|f| Reverse(f.0));
+        found.into_iter().map(|(_, k, v)| (k, v)).collect()
+    }
+}
+
+impl fmt::Display for Bookmarks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (k, v) in self.get_map() {
+            writeln!(f, "{} - {}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Check the contents of a bookmarks file. Returns an error if it cannot be parsed, and warnings
+/// about bookmarks that point to missing directories or cannot be used as aliases.
+pub(crate) fn validate(raw: &str, path_maps: &[(String, String)]) -> Result<Vec<String>, String> {
+    let entries: BTreeMap<String, Entry> =
+        toml::from_str(raw).map_err(|e| format!("{e}\nERROR: Invalid bookmarks file."))?;
+    let stored = entries
+        .into_iter()
+        .map(|(name, entry)| (name, entry.path().clone()))
+        .collect();
+    let (resolved, problems) = paths::resolve(&stored, path_maps);
+    let mut warnings: Vec<String> = problems.into_iter().map(|p| format!("WARNING: {p}")).collect();
+    for (name, path) in resolved {
+        if paths::is_glob(&path) {
+            if paths::glob(&path).is_empty() {
+                warnings.push(format!(
+                    "WARNING: Bookmark `{name}` is a pattern that matches no directories: `{path}`"
+                ));
+            }
+            continue;
+        }
+        if !Path::new(&path).is_dir() {
+            warnings.push(format!(
+                "WARNING: Bookmark `{name}` points to a missing directory: `{path}`"
+            ));
+        }
+        if !is_alias_name(&name) {
+            warnings.push(format!(
+                "WARNING: Bookmark `{name}` contains spaces or special characters and cannot be accessed through aliases."
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// The name proposed for a bookmark to a directory: the name of the directory.
+pub(crate) fn default_name(dir: &Path) -> String {
+    match dir.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => "root".to_string(),
+    }
+}
+
+/// The directories of bookmarks by their canonical path, so that other directories can be
+/// compared with them, with the name of the bookmark of each.
+pub(crate) fn bookmarked_dirs(map: &BTreeMap<String, String>) -> BTreeMap<PathBuf, String> {
+    map.iter()
+        .map(|(name, path)| {
+            let path = PathBuf::from(path);
+            (path.canonicalize().unwrap_or(path), name.clone())
+        })
+        .collect()
+}
+
+/// A name for a bookmark to `dir` that is not in `taken`. The default name is prefixed with the
+/// name of the parent directory, and numbered if that is not enough.
+pub(crate) fn unique_name(dir: &Path, taken: &BTreeSet<String>) -> String {
+    let name = default_name(dir);
+    let mut candidates = vec![name.clone()];
+    if let Some(parent) = dir.parent().filter(|p| p.parent().is_some()) {
+        candidates.push(format!("{}-{name}", default_name(parent)));
+    }
+    candidates
+        .into_iter()
+        .chain((2..).map(|n| format!("{name}-{n}")))
+        .find(|c| !taken.contains(c))
+        .expect("there are infinitely many candidates")
+}
+
+/// The path of an entry in the bookmarks file, as it is written.
+fn stored_path(item: &Item) -> Option<&str> {
+    match item.as_table_like() {
+        Some(table) => table.get("path")?.as_str(),
+        None => item.as_str(),
+    }
+}
+
+/// The error for a bookmark that does not exist, suggesting a similar name.
+pub(crate) fn not_found<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> String {
+    let mut msg = format!("ERROR: could not find bookmark `{name}`.");
+    if let Some(s) = matching::suggest(name, names) {
+        msg += format!(" Did you mean `{s}`?").as_str();
+    }
+    msg
+}
+
+/// Whether a bookmark name can be used as the name of a shell function.
+pub(crate) fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Write a file by writing to a temporary file next to it and renaming that over the original,
+/// so that the file is never left half written.
+pub(crate) fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{file_name}.tmp"));
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("ERROR: Could not write to file `{}`: {e}", path.display()))
+}
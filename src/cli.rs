@@ -1,5 +1,10 @@
 use gumdrop::Options;
 
+use crate::bookmarks::SortKey;
+use crate::export::Target;
+use crate::import::{Conflict, Source};
+use crate::output::Format;
+
 #[derive(Debug, Options)]
 pub(crate) struct Opts {
     #[options(short = "h", help = "print help message")]
@@ -8,6 +13,15 @@ pub(crate) struct Opts {
     #[options(short = "v", help = "be verbose")]
     pub(crate) verbose: bool,
 
+    #[options(short = "f", help = "output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(short = "c", meta = "FILE", help = "use this configuration file")]
+    pub(crate) config: Option<String>,
+
+    #[options(short = "d", meta = "DIR", help = "use this data directory")]
+    pub(crate) data_dir: Option<String>,
+
     #[options(command)]
     pub(crate) command: Option<Command>,
 }
@@ -16,18 +30,42 @@ pub(crate) struct Opts {
 pub(crate) enum Command {
     #[options(help = "add a bookmark to the current working directory")]
     Add(AddOpts),
+    #[options(help = "find projects in a directory and bookmark them")]
+    Scan(ScanOpts),
+    #[options(help = "import bookmarks from other programs")]
+    Import(ImportOpts),
+    #[options(help = "export bookmarks for other programs")]
+    Export(ExportOpts),
     #[options(help = "edit bookmarks in a text editor")]
     Edit(EditOpts),
-    #[options(help = "list all stored bookmarks")]
+    #[options(help = "list stored bookmarks")]
     List(ListOpts),
     #[options(help = "open a new terminal in a bookmarked location")]
     Open(OpenOpts),
-    #[options(help = "remove a bookmark with a given name")]
+    #[options(help = "print the path of a bookmark")]
+    Path(PathOpts),
+    #[options(help = "list the bookmarks containing a directory")]
+    Which(WhichOpts),
+    #[options(help = "remove bookmarks")]
     Rm(RmOpts),
+    #[options(help = "rename a bookmark")]
+    Rename(RenameOpts),
+    #[options(help = "undo the last change to the bookmarks")]
+    Undo(UndoOpts),
+    #[options(help = "list snapshots of earlier bookmarks")]
+    History(HistoryOpts),
+    #[options(help = "restore bookmarks from a snapshot")]
+    Restore(RestoreOpts),
+    #[options(help = "sync bookmarks with a git remote")]
+    Sync(SyncOpts),
     #[options(help = "commands for managing bmark configuration")]
     Config(ConfigOpts),
     #[options(help = "update shell aliases file")]
     Update(UpdateOpts),
+    #[options(help = "check configuration and bookmarks for problems")]
+    Doctor(DoctorOpts),
+    #[options(help = "print a shell completion script")]
+    Completions(CompletionsOpts),
 }
 
 #[derive(Debug, Options)]
@@ -36,6 +74,72 @@ pub(crate) struct AddOpts {
     pub(crate) help: bool,
 
     pub(crate) name: Option<String>,
+
+    #[options(help = "Read directories from stdin, one per line (or NUL-separated), optionally as `name<TAB>path`")]
+    pub(crate) stdin: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct ScanOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(no_short, meta = "N", help = "How many directories deep to search (default: 3)")]
+    pub(crate) depth: Option<usize>,
+
+    #[options(short = "g", help = "Find git repositories (the default without --marker)")]
+    pub(crate) git: bool,
+
+    #[options(short = "m", meta = "FILE", help = "Find directories containing this file (can be given multiple times)")]
+    pub(crate) marker: Vec<String>,
+
+    #[options(short = "n", help = "Only show what would be added")]
+    pub(crate) dry_run: bool,
+
+    #[options(short = "y", help = "Do not ask for confirmation")]
+    pub(crate) yes: bool,
+
+    #[options(free, required, help = "Directory to search")]
+    pub(crate) root: String,
+}
+
+#[derive(Debug, Options)]
+pub struct ImportOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(no_short, meta = "SOURCE", help = "Program to import from: gtk, bashmarks, autojump, zoxide, json or csv")]
+    pub(crate) from: Option<Source>,
+
+    #[options(short = "t", meta = "N", help = "Only import the N directories with the highest score (autojump and zoxide)")]
+    pub(crate) top: Option<usize>,
+
+    #[options(short = "s", meta = "SCORE", help = "Only import directories with at least this score (autojump and zoxide)")]
+    pub(crate) min_score: Option<f64>,
+
+    #[options(short = "c", meta = "MODE", help = "What to do when a name is taken: rename (default), skip or replace")]
+    pub(crate) conflict: Option<Conflict>,
+
+    #[options(short = "n", help = "Only show what would be added")]
+    pub(crate) dry_run: bool,
+
+    #[options(short = "y", help = "Do not ask for confirmation")]
+    pub(crate) yes: bool,
+
+    #[options(free, help = "File to import, or - for stdin (default: the file of the program)")]
+    pub(crate) file: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct ExportOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(no_short, meta = "TARGET", help = "Format to export to: gtk, html, json, csv, bashmarks or desktop")]
+    pub(crate) to: Option<Target>,
+
+    #[options(short = "o", meta = "FILE", help = "File to write, or directory for desktop (default: stdout, or where the desktop looks for gtk and desktop)")]
+    pub(crate) output: Option<String>,
 }
 
 #[derive(Debug, Options)]
@@ -48,6 +152,33 @@ pub struct EditOpts {
 pub struct ListOpts {
     #[options(short = "h", help = "print help message")]
     pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(short = "u", help = "Only list bookmarks below this directory")]
+    pub(crate) under: Option<String>,
+
+    #[options(short = "m", help = "Only list bookmarks whose directory does not exist")]
+    pub(crate) missing: bool,
+
+    #[options(short = "e", help = "Only list bookmarks whose directory exists")]
+    pub(crate) existing: bool,
+
+    #[options(short = "g", help = "Only list bookmarks with this tag")]
+    pub(crate) tag: Option<String>,
+
+    #[options(short = "s", help = "Sort by name, path, added or used (most recent first)")]
+    pub(crate) sort: Option<SortKey>,
+
+    #[options(short = "r", help = "Reverse the sort order")]
+    pub(crate) reverse: bool,
+
+    #[options(short = "t", help = "Show bookmarks as a tree of directories")]
+    pub(crate) tree: bool,
+
+    #[options(free, help = "Fuzzy pattern matched against bookmark names and paths")]
+    pub(crate) query: Option<String>,
 }
 
 #[derive(Debug, Options)]
@@ -66,14 +197,116 @@ pub struct OpenOpts {
 }
 
 #[derive(Debug, Options)]
-pub struct RmOpts {
+pub struct PathOpts {
     #[options(short = "h", help = "print help message")]
     pub(crate) help: bool,
 
-    #[options(short = "n", help = "Name of a bookmark")]
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(short = "0", long = "null", help = "End output with a NUL character instead of a newline")]
+    pub(crate) null: bool,
+
+    #[options(free, required, help = "Name of a bookmark (exact, prefix or fuzzy match)")]
     pub(crate) name: String,
 }
 
+#[derive(Debug, Options)]
+pub struct WhichOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(short = "0", long = "null", help = "Separate names with NUL characters instead of newlines")]
+    pub(crate) null: bool,
+
+    #[options(free, help = "Directory to look up (default: current directory)")]
+    pub(crate) dir: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct RmOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(short = "n", help = "Name of a bookmark (can be given multiple times)")]
+    pub(crate) name: Vec<String>,
+
+    #[options(short = "p", help = "Choose the bookmarks with the dmenu-like command")]
+    pub(crate) pick: bool,
+
+    #[options(short = "m", help = "Remove all bookmarks whose directory does not exist")]
+    pub(crate) missing: bool,
+
+    #[options(short = "y", help = "Do not ask for confirmation")]
+    pub(crate) yes: bool,
+
+    #[options(free, help = "Names of the bookmarks to remove")]
+    pub(crate) names: Vec<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct RenameOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "Current name of the bookmark")]
+    pub(crate) old: String,
+
+    #[options(free, required, help = "New name of the bookmark")]
+    pub(crate) new: String,
+}
+
+#[derive(Debug, Options)]
+pub struct UndoOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct HistoryOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+}
+
+#[derive(Debug, Options)]
+pub struct RestoreOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "Number or id of the snapshot (see `bmark history`)")]
+    pub(crate) snapshot: String,
+}
+
+#[derive(Debug, Options)]
+pub struct SyncOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(command)]
+    pub(crate) command: Option<SyncCommand>,
+}
+
+#[derive(Debug, Options)]
+pub enum SyncCommand {
+    #[options(help = "Keep the data directory in a git repository synced with a remote")]
+    Init(SyncInit),
+}
+
+#[derive(Debug, Options)]
+pub struct SyncInit {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "URL or path of the git remote")]
+    pub(crate) remote: String,
+}
+
 #[derive(Debug, Options)]
 pub struct ConfigOpts {
     #[options(short = "h", help = "print help message")]
@@ -89,6 +322,24 @@ pub struct UpdateOpts {
     pub(crate) help: bool,
 }
 
+#[derive(Debug, Options)]
+pub struct DoctorOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+}
+
+#[derive(Debug, Options)]
+pub struct CompletionsOpts {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "Shell to complete for: bash, zsh or fish")]
+    pub(crate) shell: String,
+}
+
 #[derive(Debug, Options)]
 pub enum ConfigCommand {
     #[options(help = "Show the current configuration")]
@@ -97,6 +348,12 @@ pub enum ConfigCommand {
     Create(ConfigCreate),
     #[options(help = "Edit the configuration file")]
     Edit(ConfigEdit),
+    #[options(help = "Print the value of an option")]
+    Get(ConfigGet),
+    #[options(help = "Set an option in the configuration file")]
+    Set(ConfigSet),
+    #[options(help = "Remove an option from the configuration file")]
+    Unset(ConfigUnset),
     #[options(help = "Print the command used to source the bookmark aliases file")]
     SourceCmd(ConfigSourceCmd),
 }
@@ -105,6 +362,15 @@ pub enum ConfigCommand {
 pub struct ConfigShow {
     #[options(short = "h", help = "print help message")]
     pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(short = "D", help = "Show the default configuration")]
+    pub(crate) defaults: bool,
+
+    #[options(short = "o", help = "Show where each value comes from")]
+    pub(crate) origin: bool,
 }
 
 #[derive(Debug, Options)]
@@ -119,7 +385,40 @@ pub struct ConfigEdit {
     pub(crate) help: bool,
 
     #[options(short = "E", help = "Command to launch a text editor")]
-    editor: Option<String>,
+    pub(crate) editor: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct ConfigGet {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(short = "f", help = "Output format: human, json, tsv or csv")]
+    pub(crate) format: Option<Format>,
+
+    #[options(free, required, help = "Name of the option")]
+    pub(crate) key: String,
+}
+
+#[derive(Debug, Options)]
+pub struct ConfigSet {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "Name of the option")]
+    pub(crate) key: String,
+
+    #[options(free, required, help = "New value of the option")]
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Options)]
+pub struct ConfigUnset {
+    #[options(short = "h", help = "print help message")]
+    pub(crate) help: bool,
+
+    #[options(free, required, help = "Name of the option")]
+    pub(crate) key: String,
 }
 
 #[derive(Debug, Options)]
@@ -0,0 +1,375 @@
+use gumdrop::Options;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::bookmarks::Bookmarks;
+use crate::cli;
+use crate::config::{Config, OPTIONS};
+use crate::history;
+
+/// A flag parsed from the usage text that gumdrop generates for a command.
+struct Flag {
+    names: Vec<String>,
+    meta: Option<String>,
+    help: String,
+}
+
+/// The flags, positional arguments and subcommands of a command, as listed in its usage text.
+struct Level {
+    flags: Vec<Flag>,
+    positional: Vec<String>,
+    commands: Vec<(String, String)>,
+}
+
+impl Level {
+    /// The level for a command path like `["config", "show"]`.
+    fn of(path: &[&str]) -> Option<Self> {
+        let (usage, commands) = match path {
+            [] => (cli::Opts::usage(), cli::Opts::command_list()),
+            ["config"] => (cli::ConfigOpts::usage(), cli::ConfigOpts::command_list()),
+            ["sync"] => (cli::SyncOpts::usage(), cli::SyncOpts::command_list()),
+            [cmd] => (cli::Command::command_usage(cmd)?, None),
+            ["config", cmd] => (cli::ConfigCommand::command_usage(cmd)?, None),
+            ["sync", cmd] => (cli::SyncCommand::command_usage(cmd)?, None),
+            _ => return None,
+        };
+
+        let mut level = Level {
+            flags: vec![],
+            positional: vec![],
+            commands: commands.map(described).unwrap_or_default(),
+        };
+        let mut in_positional = false;
+        for line in usage.lines() {
+            match line.trim() {
+                "Positional arguments:" => in_positional = true,
+                "Optional arguments:" => in_positional = false,
+                l if in_positional && !l.is_empty() => {
+                    level.positional.push(l.split_whitespace().next().unwrap_or_default().to_string())
+                }
+                l if l.starts_with('-') => {
+                    let (spec, help) = l.split_once("  ").unwrap_or((l, ""));
+                    let mut names = vec![];
+                    let mut meta = None;
+                    for part in spec.split(", ") {
+                        let mut words = part.split_whitespace();
+                        names.extend(words.next().map(str::to_string));
+                        meta = meta.or(words.next().map(str::to_string));
+                    }
+                    level.flags.push(Flag {
+                        names,
+                        meta,
+                        help: help.trim().to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Some(level)
+    }
+
+    fn flag(&self, name: &str) -> Option<&Flag> {
+        self.flags.iter().find(|f| f.names.iter().any(|n| n == name))
+    }
+}
+
+/// Parse a gumdrop command list into names and descriptions.
+fn described(list: &str) -> Vec<(String, String)> {
+    list.lines()
+        .filter_map(|l| {
+            let l = l.trim();
+            let (name, help) = l.split_once(' ').unwrap_or((l, ""));
+            (!name.is_empty()).then(|| (name.to_string(), help.trim().to_string()))
+        })
+        .collect()
+}
+
+/// What the shell should complete.
+enum Completion {
+    Words(Vec<(String, String)>),
+    Dirs,
+    Files,
+}
+
+/// Complete the last of `words`, the command line after `bmark`. The output starts with a line
+/// telling the shell what to complete (`words`, `dirs` or `files`), followed by the candidate
+/// words, each optionally followed by a tab and a description.
+pub(crate) fn complete(words: &[String]) -> String {
+    let (cur, done) = match words.split_last() {
+        Some((cur, done)) => (cur.as_str(), done),
+        None => ("", &[][..]),
+    };
+
+    let mut path: Vec<&str> = vec![];
+    let mut positional = 0;
+    let mut level = match Level::of(&[]) {
+        Some(l) => l,
+        None => return String::new(),
+    };
+    // The flag whose value comes next, with the name of that value
+    let mut pending: Option<(&str, String)> = None;
+    let mut globals: Vec<(&str, &str)> = vec![];
+
+    for w in done {
+        if let Some((flag, _)) = pending.take() {
+            if path.is_empty() {
+                globals.push((flag, w.as_str()));
+            }
+            continue;
+        }
+        if w.starts_with('-') && w.len() > 1 {
+            if !w.contains('=') {
+                pending = level.flag(w).and_then(|f| Some((w.as_str(), f.meta.clone()?)));
+            }
+            continue;
+        }
+        if level.commands.iter().any(|(c, _)| c == w) {
+            path.push(w);
+            positional = 0;
+            level = match Level::of(&path) {
+                Some(l) => l,
+                None => return String::new(),
+            };
+            continue;
+        }
+        positional += 1;
+    }
+
+    let config = || config_for(&globals);
+    let completion = match pending {
+        Some((_, meta)) => value(&meta, &config),
+        None if cur.starts_with('-') => Completion::Words(
+            level
+                .flags
+                .iter()
+                .flat_map(|f| f.names.iter().map(|n| (n.clone(), f.help.clone())))
+                .collect(),
+        ),
+        None if !level.commands.is_empty() => Completion::Words(level.commands.clone()),
+        None => {
+            let arg = level
+                .positional
+                .get(positional)
+                .or(level.positional.last())
+                .map(String::as_str)
+                .unwrap_or_default();
+            argument(&path, arg, cur, done, &config)
+        }
+    };
+
+    match completion {
+        Completion::Dirs => "dirs\n".to_string(),
+        Completion::Files => "files\n".to_string(),
+        Completion::Words(words) => {
+            let mut out = "words\n".to_string();
+            for (word, help) in words.iter().filter(|(w, _)| w.starts_with(cur)) {
+                out += word.as_str();
+                if !help.is_empty() {
+                    out += format!("\t{help}").as_str();
+                }
+                out.push('\n');
+            }
+            out
+        }
+    }
+}
+
+/// The configuration, taking the global `--config` and `--data-dir` flags on the command line
+/// into account.
+fn config_for(globals: &[(&str, &str)]) -> Option<Config> {
+    let mut opts = cli::Opts::parse_args_default::<&str>(&[]).ok()?;
+    for (flag, value) in globals {
+        match *flag {
+            "-c" | "--config" => opts.config = Some(value.to_string()),
+            "-d" | "--data-dir" => opts.data_dir = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Config::new(&opts).ok()
+}
+
+/// Complete the value of a flag, by the name of its value in the usage text.
+fn value(meta: &str, config: &dyn Fn() -> Option<Config>) -> Completion {
+    let words = |w: &[&str]| Completion::Words(w.iter().map(|w| (w.to_string(), String::new())).collect());
+    match meta {
+        "FORMAT" => words(&["human", "json", "tsv", "csv"]),
+        "SORT" => words(&["name", "path", "added", "used"]),
+        "SOURCE" => words(&["gtk", "bashmarks", "autojump", "zoxide", "json", "csv"]),
+        "MODE" => words(&["rename", "skip", "replace"]),
+        "TARGET" => words(&["gtk", "html", "json", "csv", "bashmarks", "desktop"]),
+        "NAME" => names(config),
+        "TAG" => tags(config),
+        "DIR" | "UNDER" => Completion::Dirs,
+        "FILE" => Completion::Files,
+        _ => Completion::Words(vec![]),
+    }
+}
+
+/// Complete a positional argument, by its name in the usage text.
+fn argument(
+    path: &[&str],
+    arg: &str,
+    cur: &str,
+    done: &[String],
+    config: &dyn Fn() -> Option<Config>,
+) -> Completion {
+    match (path, arg) {
+        (["path"], _) if cur.contains('/') => subdirs(cur, config),
+        (_, "name" | "names" | "old" | "query") => names(config),
+        (_, "dir" | "root") => Completion::Dirs,
+        (_, "file") => Completion::Files,
+        (_, "key") => Completion::Words(OPTIONS.iter().map(|o| (o.to_string(), String::new())).collect()),
+        (["config", "set"], "value") => {
+            let key = done.last().map(String::as_str).unwrap_or_default();
+            let defaults = Config::default();
+            match defaults.get_option(key) {
+                Ok(serde_json::Value::Bool(_)) => Completion::Words(vec![
+                    ("true".to_string(), String::new()),
+                    ("false".to_string(), String::new()),
+                ]),
+                _ => Completion::Words(vec![]),
+            }
+        }
+        (_, "snapshot") => match config() {
+            Some(config) => Completion::Words(
+                history::list(&config.get_bookmarks_file())
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| ((i + 1).to_string(), s.age()))
+                    .collect(),
+            ),
+            None => Completion::Words(vec![]),
+        },
+        (_, "shell") => Completion::Words(
+            ["bash", "zsh", "fish"].iter().map(|s| (s.to_string(), String::new())).collect(),
+        ),
+        _ => Completion::Words(vec![]),
+    }
+}
+
+fn names(config: &dyn Fn() -> Option<Config>) -> Completion {
+    let map = config()
+        .and_then(|c| Bookmarks::from_config(&c).try_get_map().ok())
+        .unwrap_or_default();
+    Completion::Words(map.into_iter().collect())
+}
+
+fn tags(config: &dyn Fn() -> Option<Config>) -> Completion {
+    let tags: BTreeSet<String> = match config() {
+        Some(c) => Bookmarks::from_config(&c).entries().into_iter().flat_map(|b| b.tags).collect(),
+        None => BTreeSet::new(),
+    };
+    Completion::Words(tags.into_iter().map(|t| (t, String::new())).collect())
+}
+
+/// Complete `name/rest` with the subdirectories of a bookmark.
+fn subdirs(cur: &str, config: &dyn Fn() -> Option<Config>) -> Completion {
+    let (name, rest) = cur.split_once('/').unwrap_or((cur, ""));
+    let root = match config().and_then(|c| Bookmarks::from_config(&c).try_get_map().ok()) {
+        Some(map) => match map.get(name) {
+            Some(root) => root.clone(),
+            None => return Completion::Words(vec![]),
+        },
+        None => return Completion::Words(vec![]),
+    };
+    let (parent, _) = rest.rsplit_once('/').unwrap_or(("", rest));
+    let dir = Path::new(&root).join(parent);
+    let mut words = vec![];
+    for entry in dir.read_dir().into_iter().flatten().filter_map(|e| e.ok()) {
+        if entry.path().is_dir() {
+            let sub = Path::new(parent).join(entry.file_name());
+            words.push((format!("{name}/{}/", sub.display()), String::new()));
+        }
+    }
+    words.sort();
+    Completion::Words(words)
+}
+
+/// The completion script for a shell.
+pub(crate) fn script(shell: &str) -> Result<&'static str, String> {
+    match shell {
+        "bash" => Ok(BASH),
+        "zsh" => Ok(ZSH),
+        "fish" => Ok(FISH),
+        _ => Err(format!(
+            "ERROR: Unknown shell `{shell}`, expected one of: bash, zsh, fish"
+        )),
+    }
+}
+
+static BASH: &str = r#"# bash completion for bmark
+_bmark() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local -a out
+    mapfile -t out < <(bmark __complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)
+    COMPREPLY=()
+    case "${out[0]}" in
+        dirs)
+            compopt -o filenames
+            mapfile -t COMPREPLY < <(compgen -d -- "$cur")
+            ;;
+        files)
+            compopt -o filenames
+            mapfile -t COMPREPLY < <(compgen -f -- "$cur")
+            ;;
+        *)
+            local line
+            for line in "${out[@]:1}"; do
+                COMPREPLY+=("${line%%$'\t'*}")
+            done
+            # Bookmark subdirectories are completed one level at a time
+            [[ ${#COMPREPLY[@]} -eq 1 && ${COMPREPLY[0]} == */ ]] && compopt -o nospace
+            ;;
+    esac
+}
+complete -F _bmark bmark
+"#;
+
+static ZSH: &str = r#"#compdef bmark
+# zsh completion for bmark
+_bmark() {
+    local -a out candidates
+    local line
+    out=("${(@f)$(bmark __complete "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    case "$out[1]" in
+        dirs) _path_files -/ ;;
+        files) _files ;;
+        *)
+            for line in "${(@)out[2,-1]}"; do
+                [[ -z "$line" ]] && continue
+                if [[ "$line" == *$'\t'* ]]; then
+                    candidates+=("${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}")
+                else
+                    candidates+=("${line//:/\\:}")
+                fi
+            done
+            # Bookmark subdirectories are completed one level at a time
+            local -a suffix
+            [[ -n "${(M)out[2,-1]:#*/}" ]] && suffix=(-S '')
+            _describe -t values 'bmark' candidates "${suffix[@]}"
+            ;;
+    esac
+}
+if [[ "$funcstack[1]" == "_bmark" ]]; then
+    _bmark "$@"
+else
+    compdef _bmark bmark
+fi
+"#;
+
+static FISH: &str = r#"# fish completion for bmark
+function __bmark_complete
+    set -l words (commandline -opc)
+    set -l cur (commandline -ct)
+    set -l out (bmark __complete $words[2..-1] "$cur" 2>/dev/null)
+    switch "$out[1]"
+        case dirs
+            __fish_complete_directories (commandline -ct)
+        case files
+            __fish_complete_path (commandline -ct)
+        case '*'
+            printf '%s\n' $out[2..-1]
+    end
+end
+complete -c bmark -f -a '(__bmark_complete)'
+"#;
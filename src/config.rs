@@ -0,0 +1,566 @@
+use directories::ProjectDirs;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::{env, fs};
+use toml_edit::{Document, Item};
+
+use crate::cli;
+use crate::matching;
+use crate::paths;
+use crate::output::Records;
+
+pub(crate) static BOOKMARKS_FILE: &str = "bookmarks.toml";
+pub(crate) static ALIAS_FILE: &str = "aliases.sh";
+pub(crate) static LINKS_DIR: &str = "links";
+pub(crate) static LUA_FILE: &str = "bmark.lua";
+pub(crate) static VIM_FILE: &str = "bmark.vim";
+/// The GTK bookmarks that bmark wrote last and the files it wrote them to, so they can be removed
+/// with their bookmark.
+pub(crate) static GTK_EXPORTED_FILE: &str = "gtk-exported.txt";
+/// The launchers that bmark wrote, so that only those are removed with their bookmark.
+pub(crate) static DESKTOP_EXPORTED_FILE: &str = "desktop-exported.txt";
+
+/// The options that can be set in the configuration file.
+pub(crate) static OPTIONS: &[&str] = &[
+    "data_dir",
+    "dmenu_cmd",
+    "editor_cmd",
+    "terminal_cmd",
+    "alias_prefix",
+    "display_sep",
+    "show_paths",
+    "export_vars",
+    "export_prefix",
+    "cdpath",
+    "gtk_sync",
+    "desktop_entries",
+    "vimscript",
+];
+
+/// Tables in the configuration file, which are not options with a single value.
+pub(crate) static SECTIONS: &[&str] = &["path_maps"];
+
+pub(crate) struct Config {
+    pub(crate) dmenu_cmd: String,
+    pub(crate) editor_cmd: String,
+    pub(crate) display_sep: String,
+    pub(crate) show_paths: bool,
+    pub(crate) terminal_cmd: String,
+    pub(crate) alias_prefix: String,
+    pub(crate) export_vars: bool,
+    pub(crate) export_prefix: String,
+    pub(crate) cdpath: bool,
+    pub(crate) gtk_sync: bool,
+    pub(crate) desktop_entries: bool,
+    pub(crate) vimscript: bool,
+    /// Prefix rewrites of bookmark paths, per hostname
+    pub(crate) path_maps: BTreeMap<String, BTreeMap<String, String>>,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) config_file: PathBuf,
+    pub(crate) verbose: bool,
+    origins: BTreeMap<&'static str, Origin>,
+}
+
+/// Where the value of an option came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Origin {
+    Default,
+    File,
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File => write!(f, "config file"),
+            Origin::Env(var) => write!(f, "environment variable {var}"),
+            Origin::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// The contents of a configuration file. Options that are not set are `None`.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct FileConfig {
+    pub(crate) data_dir: Option<PathBuf>,
+    pub(crate) dmenu_cmd: Option<String>,
+    pub(crate) editor_cmd: Option<String>,
+    pub(crate) terminal_cmd: Option<String>,
+    pub(crate) alias_prefix: Option<String>,
+    pub(crate) display_sep: Option<String>,
+    pub(crate) show_paths: Option<bool>,
+    pub(crate) export_vars: Option<bool>,
+    pub(crate) export_prefix: Option<String>,
+    pub(crate) cdpath: Option<bool>,
+    pub(crate) gtk_sync: Option<bool>,
+    pub(crate) desktop_entries: Option<bool>,
+    pub(crate) vimscript: Option<bool>,
+    pub(crate) path_maps: Option<BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl FileConfig {
+    /// Read and validate a configuration file. A missing file is an empty configuration.
+    /// Returns the configuration together with warnings about unknown options.
+    pub(crate) fn read(path: &PathBuf) -> Result<(Self, Vec<String>), String> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Self::default(), vec![]))
+            }
+            Err(_) => {
+                return Err(format!(
+                    "ERROR: Can not read config file: `{}`",
+                    path.display()
+                ))
+            }
+        };
+        let invalid = |e: toml::de::Error| {
+            format!("{e}\nERROR: Invalid config file: `{}`", path.display())
+        };
+
+        let table: toml::Table = toml::from_str(&raw).map_err(invalid)?;
+        let warnings = table
+            .keys()
+            .filter(|k| !OPTIONS.contains(&k.as_str()) && !SECTIONS.contains(&k.as_str()))
+            .map(|k| {
+                let mut msg = format!(
+                    "WARNING: Unknown option `{k}` in config file: `{}`.",
+                    path.display()
+                );
+                if let Some(s) = matching::suggest(k, OPTIONS.iter().chain(SECTIONS).copied()) {
+                    msg += format!(" Did you mean `{s}`?").as_str();
+                }
+                msg
+            })
+            .collect();
+
+        let config = toml::from_str(&raw).map_err(invalid)?;
+        Ok((config, warnings))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::new(),
+            dmenu_cmd: "rofi -matching fuzzy -dmenu".to_string(),
+            editor_cmd: "nvim".to_string(),
+            terminal_cmd: "kitty --detach".to_string(),
+            alias_prefix: "_".to_string(),
+            display_sep: " : ".to_string(),
+            show_paths: false,
+            export_vars: false,
+            export_prefix: "BM_".to_string(),
+            cdpath: false,
+            gtk_sync: false,
+            desktop_entries: false,
+            vimscript: false,
+            path_maps: BTreeMap::new(),
+            config_file: PathBuf::new(),
+            verbose: false,
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration by layering defaults, the config file, `BMARK_*` environment
+    /// variables and command line options, in that order.
+    pub(crate) fn new(opts: &cli::Opts) -> Result<Self, String> {
+        let mut config = Self {
+            verbose: opts.verbose,
+            ..Self::defaults()
+        };
+
+        config.config_file = match (&opts.config, env::var("BMARK_CONFIG")) {
+            (Some(file), _) => PathBuf::from(file),
+            (None, Ok(file)) if !file.is_empty() => PathBuf::from(file),
+            _ => Self::user_config_file()?,
+        };
+
+        // Read config form toml file
+        match FileConfig::read(&config.config_file) {
+            Ok((file_config, warnings)) => {
+                for w in warnings {
+                    eprintln!("{w}");
+                }
+                config.log(format!("read config file `{}`", config.config_file.display()));
+                config.apply_file(file_config);
+            }
+            // `doctor` reports a broken config file itself, and `config edit`, `set` and `unset`
+            // are needed to fix it
+            Err(_) if matches!(opts.command, Some(cli::Command::Doctor(_))) => {}
+            Err(e) if Self::edits_config(opts) => eprintln!("{e}"),
+            Err(e) => return Err(e),
+        };
+
+        config.apply_env()?;
+        config.apply_cli(opts);
+
+        config.log(format!("using data directory `{}`", config.data_dir.display()));
+        if !config.data_dir.is_dir() {
+            return Err("ERROR: Could not deternine data directory.".to_string());
+        }
+
+        Ok(config)
+    }
+
+    /// The default configuration, including the default data directory.
+    pub(crate) fn defaults() -> Self {
+        let mut config = Self::default();
+        if let Some(proj_dirs) = ProjectDirs::from("com", "bmark", "bmark") {
+            config.data_dir = PathBuf::from(proj_dirs.data_dir());
+        }
+        config
+    }
+
+    fn apply_file(&mut self, file: FileConfig) {
+        if let Some(v) = file.data_dir {
+            self.data_dir = v;
+            self.origins.insert("data_dir", Origin::File);
+        }
+        if let Some(v) = file.dmenu_cmd {
+            self.dmenu_cmd = v;
+            self.origins.insert("dmenu_cmd", Origin::File);
+        }
+        if let Some(v) = file.editor_cmd {
+            self.editor_cmd = v;
+            self.origins.insert("editor_cmd", Origin::File);
+        }
+        if let Some(v) = file.display_sep {
+            self.display_sep = v;
+            self.origins.insert("display_sep", Origin::File);
+        }
+        if let Some(v) = file.terminal_cmd {
+            self.terminal_cmd = v;
+            self.origins.insert("terminal_cmd", Origin::File);
+        }
+        if let Some(v) = file.alias_prefix {
+            self.alias_prefix = v;
+            self.origins.insert("alias_prefix", Origin::File);
+        }
+        if let Some(v) = file.show_paths {
+            self.show_paths = v;
+            self.origins.insert("show_paths", Origin::File);
+        }
+        if let Some(v) = file.export_vars {
+            self.export_vars = v;
+            self.origins.insert("export_vars", Origin::File);
+        }
+        if let Some(v) = file.export_prefix {
+            self.export_prefix = v;
+            self.origins.insert("export_prefix", Origin::File);
+        }
+        if let Some(v) = file.cdpath {
+            self.cdpath = v;
+            self.origins.insert("cdpath", Origin::File);
+        }
+        if let Some(v) = file.gtk_sync {
+            self.gtk_sync = v;
+            self.origins.insert("gtk_sync", Origin::File);
+        }
+        if let Some(v) = file.desktop_entries {
+            self.desktop_entries = v;
+            self.origins.insert("desktop_entries", Origin::File);
+        }
+        if let Some(v) = file.vimscript {
+            self.vimscript = v;
+            self.origins.insert("vimscript", Origin::File);
+        }
+        if let Some(v) = file.path_maps {
+            self.path_maps = v;
+        }
+    }
+
+    /// Set an option from a string, checking that the value has the right type.
+    pub(crate) fn set_option(&mut self, key: &str, raw: &str, origin: Origin) -> Result<(), String> {
+        let key = option_name(key)?;
+        match key {
+            "data_dir" => self.data_dir = PathBuf::from(raw),
+            "dmenu_cmd" => self.dmenu_cmd = raw.to_string(),
+            "editor_cmd" => self.editor_cmd = raw.to_string(),
+            "terminal_cmd" => self.terminal_cmd = raw.to_string(),
+            "alias_prefix" => self.alias_prefix = raw.to_string(),
+            "display_sep" => self.display_sep = raw.to_string(),
+            "show_paths" => self.show_paths = parse_bool(key, raw)?,
+            "export_vars" => self.export_vars = parse_bool(key, raw)?,
+            "export_prefix" => self.export_prefix = raw.to_string(),
+            "cdpath" => self.cdpath = parse_bool(key, raw)?,
+            "gtk_sync" => self.gtk_sync = parse_bool(key, raw)?,
+            "desktop_entries" => self.desktop_entries = parse_bool(key, raw)?,
+            "vimscript" => self.vimscript = parse_bool(key, raw)?,
+            _ => unreachable!("all options are handled"),
+        }
+        self.origins.insert(key, origin);
+        Ok(())
+    }
+
+    /// The value of every option, in the order of [`OPTIONS`].
+    pub(crate) fn values(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("data_dir", Value::from(self.data_dir.display().to_string())),
+            ("dmenu_cmd", Value::from(self.dmenu_cmd.as_str())),
+            ("editor_cmd", Value::from(self.editor_cmd.as_str())),
+            ("terminal_cmd", Value::from(self.terminal_cmd.as_str())),
+            ("alias_prefix", Value::from(self.alias_prefix.as_str())),
+            ("display_sep", Value::from(self.display_sep.as_str())),
+            ("show_paths", Value::from(self.show_paths)),
+            ("export_vars", Value::from(self.export_vars)),
+            ("export_prefix", Value::from(self.export_prefix.as_str())),
+            ("cdpath", Value::from(self.cdpath)),
+            ("gtk_sync", Value::from(self.gtk_sync)),
+            ("desktop_entries", Value::from(self.desktop_entries)),
+            ("vimscript", Value::from(self.vimscript)),
+        ]
+    }
+
+    pub(crate) fn origin(&self, key: &str) -> Origin {
+        self.origins.get(key).cloned().unwrap_or(Origin::Default)
+    }
+
+    fn edits_config(opts: &cli::Opts) -> bool {
+        matches!(
+            &opts.command,
+            Some(cli::Command::Config(cli::ConfigOpts {
+                command: Some(
+                    cli::ConfigCommand::Edit(_)
+                        | cli::ConfigCommand::Set(_)
+                        | cli::ConfigCommand::Unset(_)
+                ),
+                ..
+            }))
+        )
+    }
+
+    /// Override options with `BMARK_<OPTION>` environment variables.
+    fn apply_env(&mut self) -> Result<(), String> {
+        for key in OPTIONS {
+            let var = format!("BMARK_{}", key.to_uppercase());
+            if let Some(v) = env::var(&var).ok().filter(|v| !v.is_empty()) {
+                self.set_option(key, &v, Origin::Env(var.clone()))
+                    .map_err(|e| format!("{e} (from {var})"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Override options with global and subcommand command line options.
+    fn apply_cli(&mut self, opts: &cli::Opts) {
+        if let Some(dir) = &opts.data_dir {
+            self.data_dir = PathBuf::from(dir);
+            self.origins.insert("data_dir", Origin::Cli);
+        }
+        match &opts.command {
+            Some(cli::Command::Open(open_opts)) => {
+                if open_opts.show_paths {
+                    self.show_paths = true;
+                    self.origins.insert("show_paths", Origin::Cli);
+                }
+                if let Some(cmd) = &open_opts.cmd {
+                    self.dmenu_cmd = cmd.clone();
+                    self.origins.insert("dmenu_cmd", Origin::Cli);
+                }
+                if let Some(term) = &open_opts.terminal {
+                    self.terminal_cmd = term.clone();
+                    self.origins.insert("terminal_cmd", Origin::Cli);
+                }
+            }
+            Some(cli::Command::Config(cli::ConfigOpts {
+                command: Some(cli::ConfigCommand::Edit(edit_opts)),
+                ..
+            })) => {
+                if let Some(editor) = &edit_opts.editor {
+                    self.editor_cmd = editor.clone();
+                    self.origins.insert("editor_cmd", Origin::Cli);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Print a message to stderr when running verbosely.
+    pub(crate) fn log(&self, msg: String) {
+        if self.verbose {
+            eprintln!("bmark: {msg}");
+        }
+    }
+
+    pub(crate) fn user_config_file() -> Result<PathBuf, String> {
+        match ProjectDirs::from("com", "bmark", "bmark") {
+            Some(proj_dirs) => Ok(PathBuf::from(proj_dirs.config_dir()).join("config.toml")),
+            None => Err("ERROR: could not determine config directory".to_string()),
+        }
+    }
+
+    pub(crate) fn get_bookmarks_file(&self) -> PathBuf {
+        let mut bookmarks_file = PathBuf::from(&self.data_dir);
+        bookmarks_file.push(BOOKMARKS_FILE);
+        bookmarks_file
+    }
+
+    /// The path rewrites for this machine, from the `[path_maps]` table of its hostname.
+    pub(crate) fn host_path_maps(&self) -> Vec<(String, String)> {
+        match self.path_maps.get(&paths::hostname()) {
+            Some(maps) => maps.clone().into_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    pub(crate) fn get_alias_file(&self) -> PathBuf {
+        let mut bookmarks_file = PathBuf::from(&self.data_dir);
+        bookmarks_file.push(ALIAS_FILE);
+        bookmarks_file
+    }
+
+    /// Create the data directory, for commands that can be the first to write to it.
+    pub(crate) fn create_data_dir(&self) -> Result<(), String> {
+        fs::create_dir_all(&self.data_dir)
+            .map_err(|e| format!("ERROR: Could not create data directory: `{e}`"))
+    }
+
+    pub(crate) fn get_links_dir(&self) -> PathBuf {
+        self.data_dir.join(LINKS_DIR)
+    }
+
+    pub(crate) fn records(&self) -> Records {
+        let mut records = Records::new(OPTIONS);
+        records.push(self.values().into_iter().map(|(_, v)| v).collect());
+        records
+    }
+
+    /// One record per option, with the origin of its value.
+    pub(crate) fn origin_records(&self) -> Records {
+        let mut records = Records::new(&["option", "value", "origin"]);
+        for (key, value) in self.values() {
+            let origin = self.origin(key).to_string();
+            records.push(vec![Value::from(key), value, Value::from(origin)]);
+        }
+        records
+    }
+
+    /// Like the `Display` implementation, but with a comment telling where each value came from.
+    pub(crate) fn display_with_origins(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in self.values() {
+            out += format!("{key} = {}  # {}\n", toml_value(&value), self.origin(key)).as_str();
+        }
+        out
+    }
+
+    /// The effective value of an option.
+    pub(crate) fn get_option(&self, key: &str) -> Result<Value, String> {
+        let key = option_name(key)?;
+        Ok(self
+            .values()
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .expect("all options have a value"))
+    }
+
+    /// Set an option in the configuration file. The value is checked to have the right type, and
+    /// comments around an old value are kept.
+    pub(crate) fn set_in_file(&self, key: &str, raw: &str) -> Result<(), String> {
+        let mut checked = Self::default();
+        checked.set_option(key, raw, Origin::File)?;
+        let key = option_name(key)?;
+        let mut value = match checked.get_option(key)? {
+            Value::Bool(b) => toml_edit::Value::from(b),
+            v => toml_edit::Value::from(v.as_str().unwrap_or_default()),
+        };
+        self.edit_file(|doc| {
+            if let Some(old) = doc.get(key).and_then(Item::as_value) {
+                *value.decor_mut() = old.decor().clone();
+            }
+            doc[key] = Item::Value(value);
+        })
+    }
+
+    /// Remove an option from the configuration file, so that its default is used.
+    pub(crate) fn unset_in_file(&self, key: &str) -> Result<(), String> {
+        let key = option_name(key)?;
+        if !self.config_file.exists() {
+            return Ok(());
+        }
+        self.edit_file(|doc| {
+            doc.remove(key);
+        })
+    }
+
+    /// Change the configuration file, keeping formatting and comments of everything else.
+    fn edit_file(&self, edit: impl FnOnce(&mut Document)) -> Result<(), String> {
+        let path = &self.config_file;
+        let raw = fs::read_to_string(path).unwrap_or_default();
+        let mut doc: Document = raw
+            .parse()
+            .map_err(|e| format!("{e}\nERROR: Invalid config file: `{}`", path.display()))?;
+        edit(&mut doc);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("ERROR: Could not create config directory: {e}"))?;
+        }
+        fs::write(path, doc.to_string()).map_err(|e| {
+            format!("ERROR: Could not write config file `{}`: {e}", path.display())
+        })?;
+        self.log(format!("wrote `{}`", path.display()));
+        Ok(())
+    }
+}
+
+/// Check that `key` is a known option, suggesting similar ones if not.
+pub(crate) fn option_name(key: &str) -> Result<&'static str, String> {
+    if let Some(option) = OPTIONS.iter().find(|o| **o == key) {
+        return Ok(option);
+    }
+    let mut msg = format!("ERROR: Unknown option `{key}`.");
+    if let Some(s) = matching::suggest(key, OPTIONS.iter().copied()) {
+        msg += format!(" Did you mean `{s}`?").as_str();
+    }
+    Err(msg)
+}
+
+fn parse_bool(key: &str, raw: &str) -> Result<bool, String> {
+    match raw {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(format!("ERROR: `{key}` must be `true` or `false`, not `{raw}`.")),
+    }
+}
+
+/// Format an option value as TOML.
+fn toml_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => toml_str(s),
+        v => v.to_string(),
+    }
+}
+
+/// Quote a TOML key if it is not a valid bare key.
+fn toml_key(s: &str) -> String {
+    toml_edit::Key::new(s).display_repr().to_string()
+}
+
+/// Quote and escape a string as a TOML value.
+pub(crate) fn toml_str(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in self.values() {
+            writeln!(f, "{key} = {}", toml_value(&value))?;
+        }
+        for (host, maps) in &self.path_maps {
+            writeln!(f, "\n[path_maps.{}]", toml_key(host))?;
+            for (from, to) in maps {
+                writeln!(f, "{} = {}", toml_key(from), toml_str(to))?;
+            }
+        }
+        Ok(())
+    }
+}
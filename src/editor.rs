@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+/// A double quoted string that Lua and Vim script read the same way.
+fn quoted(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '"' => out += "\\\"",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if c.is_ascii_control() => out += format!("\\x{:02x}", c as u32).as_str(),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+/// A Lua module for Neovim with the bookmarks in a table, which defines the `:Bmark` command when
+/// it is loaded.
+pub(crate) fn lua(map: &BTreeMap<String, String>) -> String {
+    let mut out = "-- Generated by bmark, do not edit. Load it with `dofile()` in init.lua.
+local M = {}
+
+M.bookmarks = {
+"
+    .to_string();
+    for (name, path) in map {
+        out += format!("  [{}] = {},\n", quoted(name), quoted(path)).as_str();
+    }
+    out += r#"}
+
+-- The directory of a bookmark, or of `name/rest` below a bookmark
+function M.path(target)
+  if M.bookmarks[target] then
+    return M.bookmarks[target]
+  end
+  local name, rest = target:match("^([^/]+)/(.*)$")
+  if name and M.bookmarks[name] then
+    return M.bookmarks[name] .. "/" .. rest
+  end
+end
+
+-- Change the directory to a bookmark, with `cmd` "cd" (the default) or "tcd"
+function M.cd(target, cmd)
+  local path = M.path(target)
+  if not path then
+    vim.notify("bmark: could not find bookmark `" .. target .. "`", vim.log.levels.ERROR)
+    return
+  end
+  vim.cmd[cmd or "cd"](path)
+end
+
+function M.complete(arglead)
+  local name, rest = arglead:match("^([^/]+)/(.*)$")
+  if name and M.bookmarks[name] then
+    local root = M.bookmarks[name]
+    return vim.tbl_map(function(dir)
+      return name .. "/" .. dir:sub(#root + 2)
+    end, vim.fn.getcompletion(root .. "/" .. rest, "dir"))
+  end
+  local names = {}
+  for n in pairs(M.bookmarks) do
+    if vim.startswith(n, arglead) then
+      table.insert(names, n)
+    end
+  end
+  table.sort(names)
+  return names
+end
+
+vim.api.nvim_create_user_command("Bmark", function(opts)
+  M.cd(opts.args, opts.bang and "tcd" or "cd")
+end, { nargs = 1, bang = true, complete = M.complete, desc = "Change directory to a bookmark (! for tcd)" })
+
+return M
+"#;
+    out
+}
+
+/// A Vim script with the bookmarks in `g:bmark_bookmarks`, which defines the `:Bmark` command.
+pub(crate) fn vimscript(map: &BTreeMap<String, String>) -> String {
+    let mut out = "\" Generated by bmark, do not edit. Load it with `source` in your vimrc.
+let g:bmark_bookmarks = {}
+"
+    .to_string();
+    for (name, path) in map {
+        out += format!("let g:bmark_bookmarks[{}] = {}\n", quoted(name), quoted(path)).as_str();
+    }
+    out += r#"
+function! s:Path(target) abort
+  if has_key(g:bmark_bookmarks, a:target)
+    return g:bmark_bookmarks[a:target]
+  endif
+  let l:name = matchstr(a:target, '^[^/]*')
+  if has_key(g:bmark_bookmarks, l:name)
+    return g:bmark_bookmarks[l:name] . a:target[len(l:name):]
+  endif
+  return ''
+endfunction
+
+function! s:Cd(bang, target) abort
+  let l:path = s:Path(a:target)
+  if empty(l:path)
+    echoerr 'bmark: could not find bookmark `' . a:target . '`'
+    return
+  endif
+  execute (a:bang ? 'tcd ' : 'cd ') . fnameescape(l:path)
+endfunction
+
+function! s:Complete(arglead, cmdline, cursorpos) abort
+  let l:name = matchstr(a:arglead, '^[^/]*')
+  if a:arglead =~# '/' && has_key(g:bmark_bookmarks, l:name)
+    let l:root = g:bmark_bookmarks[l:name]
+    let l:dirs = getcompletion(l:root . a:arglead[len(l:name):], 'dir')
+    return map(l:dirs, 'l:name . v:val[len(l:root):]')
+  endif
+  return filter(sort(keys(g:bmark_bookmarks)), 'stridx(v:val, a:arglead) == 0')
+endfunction
+
+command! -bang -nargs=1 -complete=customlist,s:Complete Bmark call s:Cd(<bang>0, <q-args>)
+"#;
+    out
+}
@@ -0,0 +1,350 @@
+use directories::BaseDirs;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::bookmarks::{self, Bookmark, Entry};
+use crate::output::Records;
+
+/// Formats that bookmarks can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// The GTK file chooser and file manager bookmarks
+    Gtk,
+    /// A Netscape bookmark file, which browsers can import
+    Html,
+    Json,
+    Csv,
+    Bashmarks,
+    /// A freedesktop `.desktop` launcher for every bookmark
+    Desktop,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gtk" => Ok(Target::Gtk),
+            "html" => Ok(Target::Html),
+            "json" => Ok(Target::Json),
+            "csv" => Ok(Target::Csv),
+            "bashmarks" => Ok(Target::Bashmarks),
+            "desktop" => Ok(Target::Desktop),
+            _ => Err(format!(
+                "unknown target `{s}`, expected one of: gtk, html, json, csv, bashmarks, desktop"
+            )),
+        }
+    }
+}
+
+/// The `file://` URI of a path.
+pub(crate) fn file_uri(path: &str) -> String {
+    let mut uri = "file://".to_string();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            _ => uri += format!("%{b:02X}").as_str(),
+        }
+    }
+    uri
+}
+
+/// Merge bookmarks into the contents of a GTK bookmarks file, where `previous` are the entries
+/// that bmark wrote before. Those entries are updated, or removed when they no longer belong to a
+/// bookmark, and bookmarks without an entry are added. Entries that bmark did not write are kept
+/// as they are, even for a bookmarked directory. Returns the contents and the entries that are
+/// now bmark's.
+pub(crate) fn gtk(
+    bookmarks: &[Bookmark],
+    existing: &str,
+    previous: &BTreeSet<String>,
+) -> (String, BTreeSet<String>) {
+    let lines: Vec<(&str, &str)> = existing
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| (l.split(' ').next().unwrap_or_default(), l))
+        .collect();
+    let theirs: BTreeSet<&str> = lines
+        .iter()
+        .map(|(uri, _)| *uri)
+        .filter(|uri| !previous.contains(*uri))
+        .collect();
+    // A directory that already has an entry of the user's own gets no second one
+    let ours: Vec<(String, &str)> = bookmarks
+        .iter()
+        .map(|b| (file_uri(&b.path), b.name.as_str()))
+        .filter(|(uri, _)| !theirs.contains(uri.as_str()))
+        .collect();
+
+    let mut owned = BTreeSet::new();
+    let mut out = String::new();
+    let mut add = |uri: &String, name: &str, out: &mut String| {
+        if owned.insert(uri.clone()) {
+            *out += format!("{uri} {name}\n").as_str();
+        }
+    };
+    for (uri, line) in lines {
+        if theirs.contains(uri) {
+            out += line;
+            out.push('\n');
+        } else if let Some((uri, name)) = ours.iter().find(|(u, _)| u == uri) {
+            add(uri, name, &mut out);
+        }
+    }
+    for (uri, name) in &ours {
+        add(uri, name, &mut out);
+    }
+    (out, owned)
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A Netscape bookmark file with all bookmarks in a `bmark` folder, and a folder for every tag.
+/// Bookmarks with several tags are in several folders.
+pub(crate) fn html(bookmarks: &[Bookmark]) -> String {
+    let link = |b: &Bookmark, indent: &str| {
+        let date = b.added.map(|a| format!(" ADD_DATE=\"{a}\"")).unwrap_or_default();
+        format!(
+            "{indent}<DT><A HREF=\"{}\"{date}>{}</A>\n",
+            escape_html(&file_uri(&b.path)),
+            escape_html(&b.name)
+        )
+    };
+    let mut tagged: BTreeMap<&String, Vec<&Bookmark>> = BTreeMap::new();
+    for b in bookmarks {
+        for t in &b.tags {
+            tagged.entry(t).or_default().push(b);
+        }
+    }
+
+    let mut out = "<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file by bmark. -->
+<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3>bmark</H3>
+    <DL><p>
+"
+    .to_string();
+    for (tag, bookmarks) in tagged {
+        out += format!("        <DT><H3>{}</H3>\n        <DL><p>\n", escape_html(tag)).as_str();
+        for b in bookmarks {
+            out += link(b, "            ").as_str();
+        }
+        out += "        </DL><p>\n";
+    }
+    for b in bookmarks.iter().filter(|b| b.tags.is_empty()) {
+        out += link(b, "        ").as_str();
+    }
+    out += "    </DL><p>\n</DL><p>\n";
+    out
+}
+
+/// The stored entries as records, with the paths exactly as they are in the bookmarks file so
+/// that `bmark import` can read them back.
+pub(crate) fn records(entries: &BTreeMap<String, Entry>) -> Records {
+    let mut records = Records::new(&["name", "path", "tags"]);
+    for (name, entry) in entries {
+        let tags = match entry {
+            Entry::Path(_) => vec![],
+            Entry::Table { tags, .. } => tags.clone(),
+        };
+        records.push(vec![
+            Value::from(name.as_str()),
+            Value::from(entry.path().as_str()),
+            Value::from(tags),
+        ]);
+    }
+    records
+}
+
+/// A bashmarks `~/.sdirs` file. Bashmarks only allows letters, digits and `_` in names, so the
+/// names of bookmarks with other characters are returned separately.
+pub(crate) fn bashmarks(bookmarks: &[Bookmark]) -> (String, Vec<&str>) {
+    let mut out = String::new();
+    let mut skipped = vec![];
+    for b in bookmarks {
+        if !bookmarks::is_alias_name(&b.name) || b.name.contains(['-', '.']) {
+            skipped.push(b.name.as_str());
+            continue;
+        }
+        let path = b
+            .path
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('`', "\\`");
+        out += format!("export DIR_{}=\"{path}\"\n", b.name).as_str();
+    }
+    (out, skipped)
+}
+
+/// The directory for the launchers of bookmarks, where desktop environments look for
+/// applications.
+pub(crate) fn desktop_dir() -> Option<PathBuf> {
+    Some(BaseDirs::new()?.data_dir().join("applications").join("bmark"))
+}
+
+/// The file name of the launcher of a bookmark. Characters that are not allowed in desktop file
+/// ids are replaced.
+pub(crate) fn desktop_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("{name}.desktop")
+}
+
+/// A value of a desktop entry, which cannot span lines.
+fn desktop_string(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// A desktop entry that launches `command` with `sh` to open the directory of a bookmark.
+pub(crate) fn desktop_entry(b: &Bookmark, command: &str) -> String {
+    // Quoted as an argument of `Exec`, where `%` starts a field code
+    let quoted: String = command
+        .chars()
+        .flat_map(|c| match c {
+            '"' | '`' | '$' | '\\' => vec!['\\', c],
+            '%' => vec!['%', '%'],
+            c => vec![c],
+        })
+        .collect();
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name={}
+Comment={}
+Exec={}
+Icon=folder
+Terminal=false
+Categories=Utility;
+",
+        desktop_string(&b.name),
+        desktop_string(&format!("Open {}", b.path)),
+        desktop_string(&format!("sh -c \"{quoted}\"")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{self, Source};
+    use crate::output::Format;
+
+    fn bookmark(name: &str, path: &str) -> Bookmark {
+        Bookmark::new(name.to_string(), path.to_string())
+    }
+
+    #[test]
+    fn gtk_merge_adds_updates_and_removes_own_entries() {
+        let bookmarks = [
+            bookmark("src", "/home/me/src"),
+            bookmark("docs", "/home/me/My Docs"),
+        ];
+        let existing = "file:///usr Usr\nfile:///home/me/src old-name\nfile:///home/me/gone gone\n";
+        let previous = BTreeSet::from([
+            "file:///home/me/src".to_string(),
+            "file:///home/me/gone".to_string(),
+        ]);
+        let (contents, owned) = gtk(&bookmarks, existing, &previous);
+        assert_eq!(
+            contents,
+            "file:///usr Usr\nfile:///home/me/src src\nfile:///home/me/My%20Docs docs\n"
+        );
+        assert_eq!(
+            owned,
+            BTreeSet::from([
+                "file:///home/me/src".to_string(),
+                "file:///home/me/My%20Docs".to_string()
+            ])
+        );
+        assert_eq!(
+            gtk(&bookmarks, &contents, &owned),
+            (contents.clone(), owned)
+        );
+    }
+
+    #[test]
+    fn gtk_merge_keeps_entries_of_the_user() {
+        let bookmarks = [bookmark("src", "/home/me/src"), bookmark("tmp", "/tmp")];
+        let existing = "file:///home/me/src My Sources\nfile:///home/me/src duplicate\n";
+        let (contents, owned) = gtk(&bookmarks, existing, &BTreeSet::new());
+        assert_eq!(
+            contents,
+            "file:///home/me/src My Sources\nfile:///home/me/src duplicate\nfile:///tmp tmp\n"
+        );
+        assert_eq!(owned, BTreeSet::from(["file:///tmp".to_string()]));
+
+        // Removing the bookmark does not remove the entry that was there before
+        let (contents, owned) = gtk(&bookmarks[1..], &contents, &owned);
+        assert!(
+            contents.starts_with("file:///home/me/src My Sources\n"),
+            "{contents}"
+        );
+        assert_eq!(owned, BTreeSet::from(["file:///tmp".to_string()]));
+        let (contents, owned) = gtk(&[], &contents, &owned);
+        assert_eq!(
+            contents,
+            "file:///home/me/src My Sources\nfile:///home/me/src duplicate\n"
+        );
+        assert!(owned.is_empty());
+    }
+
+    #[test]
+    fn csv_and_json_round_trip() {
+        let entries = BTreeMap::from([
+            ("plain".to_string(), Entry::Path("~/src".to_string())),
+            (
+                "odd, \"name\"".to_string(),
+                Entry::Table {
+                    path: "/tmp/a,b\n\"c\"".to_string(),
+                    tags: vec!["one".to_string(), "two words".to_string()],
+                },
+            ),
+        ]);
+        let csv = records(&entries).render(Format::Csv);
+        let imported = import::parse(Source::Csv, &csv).unwrap();
+        let back: BTreeMap<String, Entry> = imported
+            .iter()
+            .map(|i| (i.name.clone().unwrap(), i.entry()))
+            .collect();
+        assert_eq!(back, entries);
+
+        let json = records(&entries).render(Format::Json);
+        let imported = import::parse(Source::Json, &json).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(
+            imported
+                .iter()
+                .map(|i| (i.name.clone().unwrap(), i.entry()))
+                .collect::<BTreeMap<_, _>>(),
+            entries
+        );
+    }
+
+    #[test]
+    fn file_uris_are_percent_encoded() {
+        assert_eq!(
+            file_uri("/home/me/My Docs/été"),
+            "file:///home/me/My%20Docs/%C3%A9t%C3%A9"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bookmarks::Entry;
+
+static HISTORY_DIR: &str = "history";
+
+/// How many snapshots are kept before the oldest ones are deleted.
+static HISTORY_SIZE: usize = 50;
+
+/// A copy of the bookmarks file from before it was changed.
+pub(crate) struct Snapshot {
+    /// Milliseconds since the unix epoch when the snapshot was taken, used as its file name
+    pub(crate) id: u128,
+    pub(crate) file: PathBuf,
+}
+
+impl Snapshot {
+    pub(crate) fn contents(&self) -> String {
+        fs::read_to_string(&self.file).unwrap_or_default()
+    }
+
+    /// How long ago the snapshot was taken, like "5 minutes ago".
+    pub(crate) fn age(&self) -> String {
+        let secs = (now_millis().saturating_sub(self.id) / 1000) as u64;
+        let (n, unit) = match secs {
+            0..=59 => return "just now".to_string(),
+            60..=3599 => (secs / 60, "minute"),
+            3600..=86399 => (secs / 3600, "hour"),
+            _ => (secs / 86400, "day"),
+        };
+        format!("{n} {unit}{} ago", if n == 1 { "" } else { "s" })
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn history_dir(bookmarks_file: &Path) -> PathBuf {
+    bookmarks_file.with_file_name(HISTORY_DIR)
+}
+
+/// Save a snapshot of the bookmarks file, and delete the oldest snapshots if there are too many.
+pub(crate) fn save(bookmarks_file: &Path, contents: &str) -> Result<(), String> {
+    let dir = history_dir(bookmarks_file);
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("ERROR: Could not create history directory: {e}"))?;
+
+    // Make sure that two snapshots taken in the same millisecond get different names
+    let mut id = now_millis();
+    while dir.join(format!("{id}.toml")).exists() {
+        id += 1;
+    }
+    fs::write(dir.join(format!("{id}.toml")), contents)
+        .map_err(|e| format!("ERROR: Could not save snapshot of bookmarks: {e}"))?;
+
+    for old in list(bookmarks_file).iter().skip(HISTORY_SIZE) {
+        let _ = fs::remove_file(&old.file);
+    }
+    Ok(())
+}
+
+/// All snapshots, newest first.
+pub(crate) fn list(bookmarks_file: &Path) -> Vec<Snapshot> {
+    let mut snapshots: Vec<Snapshot> = match fs::read_dir(history_dir(bookmarks_file)) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let file = e.path();
+                let id = file.file_stem()?.to_str()?.parse().ok()?;
+                Some(Snapshot { id, file })
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.id));
+    snapshots
+}
+
+/// Find a snapshot by its number in the history (1 is the newest) or by its id.
+pub(crate) fn find(bookmarks_file: &Path, snapshot: &str) -> Result<Snapshot, String> {
+    let snapshots = list(bookmarks_file);
+    let n: u128 = snapshot
+        .parse()
+        .map_err(|_| format!("ERROR: Invalid snapshot `{snapshot}`, see `bmark history`."))?;
+    let index = usize::try_from(n).ok().filter(|i| (1..=snapshots.len()).contains(i));
+    snapshots
+        .into_iter()
+        .enumerate()
+        .find(|(i, s)| Some(i + 1) == index || s.id == n)
+        .map(|(_, s)| s)
+        .ok_or(format!("ERROR: Could not find snapshot `{snapshot}`, see `bmark history`."))
+}
+
+/// The bookmarks that were added (`+`), removed (`-`) or changed (`~`) between two versions of the
+/// bookmarks file.
+pub(crate) fn changes(before: &str, after: &str) -> Vec<String> {
+    let parse = |raw: &str| -> BTreeMap<String, Entry> { toml::from_str(raw).unwrap_or_default() };
+    let (before, after) = (parse(before), parse(after));
+
+    let mut changes = vec![];
+    for (name, entry) in &after {
+        match before.get(name) {
+            None => changes.push(format!("+{name}")),
+            Some(old) if old != entry => changes.push(format!("~{name}")),
+            _ => {}
+        }
+    }
+    for name in before.keys() {
+        if !after.contains_key(name) {
+            changes.push(format!("-{name}"));
+        }
+    }
+    changes
+}
@@ -0,0 +1,380 @@
+use directories::BaseDirs;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::bookmarks::Entry;
+use crate::paths;
+
+/// Programs that bookmarks can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// The GTK file chooser and file manager bookmarks
+    Gtk,
+    Bashmarks,
+    Autojump,
+    /// The output of `zoxide query -ls`
+    Zoxide,
+    /// Bookmarks exported by `bmark export --to json`
+    Json,
+    /// Bookmarks exported by `bmark export --to csv`
+    Csv,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gtk" => Ok(Source::Gtk),
+            "bashmarks" => Ok(Source::Bashmarks),
+            "autojump" => Ok(Source::Autojump),
+            "zoxide" => Ok(Source::Zoxide),
+            "json" => Ok(Source::Json),
+            "csv" => Ok(Source::Csv),
+            _ => Err(format!(
+                "unknown source `{s}`, expected one of: gtk, bashmarks, autojump, zoxide, json, csv"
+            )),
+        }
+    }
+}
+
+impl Source {
+    /// Whether the program scores directories by how much they are used.
+    pub(crate) fn is_weighted(self) -> bool {
+        matches!(self, Source::Autojump | Source::Zoxide)
+    }
+
+    /// Whether the bookmarks come from bmark itself, so their paths and tags are kept exactly.
+    pub(crate) fn is_exact(self) -> bool {
+        matches!(self, Source::Json | Source::Csv)
+    }
+
+    /// The file where the program keeps its bookmarks, if there is one that can be read.
+    pub(crate) fn default_file(self) -> Option<PathBuf> {
+        let dirs = BaseDirs::new()?;
+        match self {
+            Source::Gtk => Some(dirs.config_dir().join("gtk-3.0").join("bookmarks")),
+            Source::Bashmarks => Some(dirs.home_dir().join(".sdirs")),
+            Source::Autojump => Some(dirs.data_dir().join("autojump").join("autojump.txt")),
+            Source::Zoxide | Source::Json | Source::Csv => None,
+        }
+    }
+}
+
+/// What to do when an imported bookmark has a name that is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Conflict {
+    /// Add a number to the name
+    #[default]
+    Rename,
+    Skip,
+    /// Point the existing bookmark to the imported directory
+    Replace,
+}
+
+impl FromStr for Conflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(Conflict::Rename),
+            "skip" => Ok(Conflict::Skip),
+            "replace" => Ok(Conflict::Replace),
+            _ => Err(format!(
+                "unknown conflict mode `{s}`, expected one of: rename, skip, replace"
+            )),
+        }
+    }
+}
+
+/// A directory found in the data of another program.
+#[derive(Debug)]
+pub(crate) struct Imported {
+    /// The name the program gave the directory, if any
+    pub(crate) name: Option<String>,
+    pub(crate) path: String,
+    pub(crate) score: Option<f64>,
+    pub(crate) tags: Vec<String>,
+}
+
+impl Imported {
+    /// The entry to store in the bookmarks file, with the path as it was given.
+    pub(crate) fn entry(&self) -> Entry {
+        match self.tags.is_empty() {
+            true => Entry::Path(self.path.clone()),
+            false => Entry::Table {
+                path: self.path.clone(),
+                tags: self.tags.clone(),
+            },
+        }
+    }
+}
+
+/// Read the directories from the data of another program. Lines that cannot be understood are
+/// skipped, but exports of bmark have to be valid.
+pub(crate) fn parse(source: Source, raw: &str) -> Result<Vec<Imported>, String> {
+    match source {
+        Source::Json => return parse_json(raw),
+        Source::Csv => return parse_csv(raw),
+        _ => {}
+    }
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match source {
+            Source::Gtk => parse_gtk(line),
+            Source::Bashmarks => parse_bashmarks(line),
+            Source::Autojump => parse_scored(line.split_once('\t')?),
+            Source::Zoxide => parse_scored(line.split_once(char::is_whitespace)?),
+            Source::Json | Source::Csv => unreachable!("parsed as a whole"),
+        })
+        .collect())
+}
+
+/// A line like `file:///home/me/My%20Documents Documents`, where the label is optional.
+fn parse_gtk(line: &str) -> Option<Imported> {
+    let (uri, label) = match line.split_once(' ') {
+        Some((uri, label)) => (uri, Some(label.trim())),
+        None => (line, None),
+    };
+    // Skip the host, which is empty for local files
+    let rest = uri.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    Some(Imported {
+        name: label.filter(|l| !l.is_empty()).map(str::to_string),
+        path: percent_decode(path),
+        score: None,
+        tags: vec![],
+    })
+}
+
+/// A line like `export DIR_name="$HOME/path"`.
+fn parse_bashmarks(line: &str) -> Option<Imported> {
+    let (name, path) = line.strip_prefix("export DIR_")?.split_once('=')?;
+    let path = path.trim_matches('"');
+    Some(Imported {
+        name: Some(name.to_string()),
+        path: paths::expand(path, &[]),
+        score: None,
+        tags: vec![],
+    })
+}
+
+/// A score and a path.
+fn parse_scored((score, path): (&str, &str)) -> Option<Imported> {
+    Some(Imported {
+        name: None,
+        path: path.trim().to_string(),
+        score: Some(score.trim().parse().ok()?),
+        tags: vec![],
+    })
+}
+
+/// An array of objects with a `name`, a `path` and optionally `tags`.
+fn parse_json(raw: &str) -> Result<Vec<Imported>, String> {
+    let invalid = |e: String| format!("ERROR: Invalid JSON bookmarks: {e}");
+    let records: Vec<Value> = serde_json::from_str(raw).map_err(|e| invalid(e.to_string()))?;
+    records
+        .iter()
+        .map(|r| {
+            let field = |key: &str| match r.get(key).and_then(Value::as_str) {
+                Some(s) => Ok(s.to_string()),
+                None => Err(invalid(format!("a bookmark has no `{key}`"))),
+            };
+            let tags = r.get("tags").and_then(Value::as_array).cloned().unwrap_or_default();
+            Ok(Imported {
+                name: Some(field("name")?),
+                path: field("path")?,
+                score: None,
+                tags: tags.iter().filter_map(|t| Some(t.as_str()?.to_string())).collect(),
+            })
+        })
+        .collect()
+}
+
+/// A header line with `name`, `path` and optionally `tags` columns, where tags are separated by
+/// commas.
+fn parse_csv(raw: &str) -> Result<Vec<Imported>, String> {
+    let mut rows = csv_rows(raw).into_iter();
+    let header = rows.next().unwrap_or_default();
+    let column = |key: &str| header.iter().position(|h| h == key);
+    let (Some(name), Some(path)) = (column("name"), column("path")) else {
+        return Err("ERROR: Invalid CSV bookmarks: the header needs `name` and `path` columns.".to_string());
+    };
+    let tags = column("tags");
+    Ok(rows
+        .filter(|row| row.len() > name.max(path))
+        .map(|row| Imported {
+            name: Some(row[name].clone()),
+            path: row[path].clone(),
+            score: None,
+            tags: tags
+                .and_then(|t| row.get(t))
+                .map(|t| t.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Split CSV into rows of fields. Quoted fields can contain commas, newlines and `""` quotes.
+fn csv_rows(raw: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = s.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        let hex = after.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(decoded) if b == b'%' => {
+                bytes.push(decoded);
+                rest = &after[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(found: &[Imported]) -> Vec<(Option<&str>, &str, Option<f64>)> {
+        found
+            .iter()
+            .map(|f| (f.name.as_deref(), f.path.as_str(), f.score))
+            .collect()
+    }
+
+    #[test]
+    fn gtk_uris_and_labels() {
+        let raw = "file:///home/me/My%20Documents Documents\n\
+                   file:///home/me/src\n\
+                   file://host/srv/share Share with spaces\n\
+                   file:///tmp/%C3%A9t%C3%A9 \n\
+                   \n\
+                   sftp://server/home/me remote\n";
+        let found = parse(Source::Gtk, raw).unwrap();
+        assert_eq!(
+            summary(&found),
+            [
+                (Some("Documents"), "/home/me/My Documents", None),
+                (None, "/home/me/src", None),
+                (Some("Share with spaces"), "/srv/share", None),
+                (None, "/tmp/été", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("/a%2Fb%2"), "/a/b%2");
+        assert_eq!(percent_decode("/100%zz"), "/100%zz");
+    }
+
+    #[test]
+    fn bashmarks_exports() {
+        let raw =
+            "export DIR_src=\"$HOME/src\"\nexport DIR_tmp=\"/tmp\"\n# comment\nexport PATH=/bin\n";
+        let found = parse(Source::Bashmarks, raw).unwrap();
+        let src = paths::expand("$HOME/src", &[]);
+        assert_eq!(
+            summary(&found),
+            [
+                (Some("src"), src.as_str(), None),
+                (Some("tmp"), "/tmp", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn autojump_and_zoxide_scores() {
+        let autojump = "10.5\t/home/me/src\n2\t/home/me/My Documents\nnot a score\t/x\n/no/score\n";
+        assert_eq!(
+            summary(&parse(Source::Autojump, autojump).unwrap()),
+            [
+                (None, "/home/me/src", Some(10.5)),
+                (None, "/home/me/My Documents", Some(2.0)),
+            ]
+        );
+
+        let zoxide = "  12.0 /home/me/src\n   0.5 /home/me/My Documents\n";
+        assert_eq!(
+            summary(&parse(Source::Zoxide, zoxide).unwrap()),
+            [
+                (None, "/home/me/src", Some(12.0)),
+                (None, "/home/me/My Documents", Some(0.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let raw =
+            "name,path,tags\r\nsrc,/home/me/src,\"rust,cli\"\n\"a, b\",\"/x/\"\"q\"\"\nline\",\n";
+        assert_eq!(
+            csv_rows(raw),
+            [
+                vec!["name", "path", "tags"],
+                vec!["src", "/home/me/src", "rust,cli"],
+                vec!["a, b", "/x/\"q\"\nline", ""],
+            ]
+        );
+        let found = parse(Source::Csv, raw).unwrap();
+        assert_eq!(found[0].tags, ["rust", "cli"]);
+        assert!(found[1].tags.is_empty());
+        assert_eq!(found[1].path, "/x/\"q\"\nline");
+    }
+
+    #[test]
+    fn csv_needs_name_and_path_columns() {
+        assert!(parse(Source::Csv, "name,tags\nsrc,rust\n").is_err());
+        assert!(parse(Source::Csv, "").is_err());
+    }
+
+    #[test]
+    fn json_records() {
+        let raw = r#"[{"name": "src", "path": "~/src", "tags": ["rust"]}, {"name": "tmp", "path": "/tmp"}]"#;
+        let found = parse(Source::Json, raw).unwrap();
+        assert_eq!(
+            summary(&found),
+            [(Some("src"), "~/src", None), (Some("tmp"), "/tmp", None)]
+        );
+        assert_eq!(found[0].tags, ["rust"]);
+        assert!(parse(Source::Json, r#"[{"name": "src"}]"#).is_err());
+    }
+}
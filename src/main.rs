@@ -1,436 +1,654 @@
+mod bookmarks;
 mod cli;
+mod complete;
+mod config;
+mod editor;
+mod export;
+mod history;
+mod import;
+mod matching;
+mod output;
+mod paths;
+mod scan;
+mod sync;
+mod tree;
 
-use directories::ProjectDirs;
+use bookmarks::{Bookmark, Bookmarks, Entry, Query};
+use config::{
+    Config, FileConfig, ALIAS_FILE, DESKTOP_EXPORTED_FILE, GTK_EXPORTED_FILE, LUA_FILE, VIM_FILE,
+};
 use gumdrop::Options;
-use std::collections::{BTreeMap, HashMap};
-use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process::{exit, Command};
+use export::Target;
+use output::{Format, Records, Style};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
 use std::{env, fs};
 
-static BOOKMARKS_FILE: &str = "bookmarks.toml";
-static ALIAS_FILE: &str = "aliases.sh";
-
-struct Bookmarks {
-    file: PathBuf,
-}
-
-impl Bookmarks {
-    fn from(path: PathBuf) -> Bookmarks {
-        Bookmarks { file: path }
-    }
-    fn from_config(config: &Config) -> Bookmarks {
-        Bookmarks {
-            file: config.get_bookmarks_file(),
-        }
-    }
-    fn get_raw(&self) -> String {
-        let mut contents = String::new();
-        match File::open(&self.file) {
-            Ok(mut file) => {
-                if let Err(_) = file.read_to_string(&mut contents) {
-                    eprintln!(
-                        "ERROR: opened, but could not read from bookmarks file: `{}`",
-                        self.file.display()
-                    );
-                    exit(1);
-                }
-            }
-            Err(_) => {
-                eprintln!(
-                    "ERROR: could not open bookmarks file: `{}`",
-                    self.file.display()
-                );
-                exit(1);
+// Add: source_cmd subcommand to output the command to source the alias file
+fn bmark_config(config: &Config, subcommand: cli::ConfigCommand, format: Format) -> BmarkResult {
+    match subcommand {
+        cli::ConfigCommand::Show(show_opts) => {
+            let format = show_opts.format.unwrap_or(format);
+            let shown = if show_opts.defaults { &Config::defaults() } else { config };
+            match (format, show_opts.origin) {
+                (Format::Human, false) => print!("{shown}"),
+                (Format::Human, true) => print!("{}", shown.display_with_origins()),
+                (f, false) => print!("{}", shown.records().render_single(f)),
+                (f, true) => print!("{}", shown.origin_records().render(f)),
             }
         }
-        contents
-    }
-    fn get_map(&self) -> BTreeMap<String, String> {
-        match toml::from_str(&self.get_raw()) {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("{e}");
-                eprintln!(
-                    "ERROR: Could not parse bookmarks file: `{}`",
-                    self.file.display()
-                );
+        cli::ConfigCommand::Create(_) => {
+            let config_file = config.config_file.clone();
+            if config_file.exists() {
+                eprintln!("ERROR: Cannot create default config file, a config file already exists at `{}`.", 
+                          config_file.display());
                 exit(1);
             }
-        }
-    }
-    fn readable_with_paths(&self, sep: &String) -> String {
-        let map = self.get_map();
-        let mut max_len: usize = 0;
-        for l in map.keys() {
-            if l.len() > max_len {
-                max_len = l.len();
+            fs::create_dir_all(config_file.parent().expect("No parrent of config file."))
+                .expect("Could not create config directory.");
+            if let Err(e) = fs::write(&config_file, Config::defaults().to_string()) {
+                eprintln!("ERROR: Could not write to config file : {e}");
+                exit(1);
             }
         }
-        let mut res = String::new();
-        for (k, v) in map {
-            let mut padding = "".to_string();
-            for _ in 0..(max_len - k.len()) {
-                padding.push(' ')
+        cli::ConfigCommand::Get(get_opts) => {
+            let value = config.get_option(&get_opts.key)?;
+            match get_opts.format.unwrap_or(format) {
+                Format::Human => match value {
+                    Value::String(s) => println!("{s}"),
+                    v => println!("{v}"),
+                },
+                f => {
+                    let mut records = Records::new(&["option", "value", "origin"]);
+                    let origin = config.origin(&get_opts.key).to_string();
+                    records.push(vec![Value::from(get_opts.key), value, Value::from(origin)]);
+                    print!("{}", records.render_single(f));
+                }
             }
-            res += format!("{}{}{}{}\n", k, padding, sep, v).as_str();
         }
-        res
-    }
+        cli::ConfigCommand::Set(set_opts) => config.set_in_file(&set_opts.key, &set_opts.value)?,
+        cli::ConfigCommand::Unset(unset_opts) => config.unset_in_file(&unset_opts.key)?,
+        cli::ConfigCommand::Edit(_) => {
+            let path = config.config_file.clone();
 
-    fn readable(&self) -> String {
-        let map = self.get_map();
-        let mut max_len: usize = 0;
-        for l in map.keys() {
-            if l.len() > max_len {
-                max_len = l.len();
+            if !path.exists() {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
             }
+            let editor_cmd = config.editor_cmd.clone() + " " + path.to_str().unwrap();
+            config.log(format!("running `{editor_cmd}`"));
+            Command::new("sh")
+                .arg("-c")
+                .arg(editor_cmd)
+                .status()
+                .expect("ERROR: Failed to execute editor command.");
+        }
+        cli::ConfigCommand::SourceCmd(_) => {
+            println!("source \"{}/{}\"", config.data_dir.display(), ALIAS_FILE,);
+            exit(0);
         }
-        map.keys().map(|k| k.to_string() + "\n").collect()
     }
+
+    Ok(())
 }
 
-impl fmt::Display for Bookmarks {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (k, v) in self.get_map() {
-            write!(f, "{} - {}\n", k, v)?;
-        }
-        Ok(())
+// Make sure that there are no duplicates
+fn bmark_add(config: &Config, name: Option<String>) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
+    let bookmarks_file = bookmarks.file.clone();
+
+    let data_dir = bookmarks_file
+        .parent()
+        .expect("There should always be a parrent in the path.");
+
+    if !data_dir.exists() {
+        if let Err(e) = fs::create_dir_all(data_dir) {
+            return Err(format!("ERROR: Could not create data directory: `{}`", e));
+        };
     }
-}
 
-struct Config {
-    dmenu_cmd: String,
-    editor_cmd: String,
-    display_sep: String,
-    show_paths: bool,
-    terminal_cmd: String,
-    alias_prefix: String,
-    data_dir: PathBuf,
-}
+    let cwd = env::current_dir().unwrap();
+    let bmark_name = match name {
+        Some(n) => n,
+        None => bookmarks::default_name(&cwd),
+    };
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            data_dir: PathBuf::new(),
-            dmenu_cmd: "rofi -matching fuzzy -dmenu".to_string(),
-            editor_cmd: "nvim".to_string(),
-            terminal_cmd: "kitty --detach".to_string(),
-            alias_prefix: "_".to_string(),
-            display_sep: " : ".to_string(),
-            show_paths: false,
-        }
+    if !bookmarks::is_alias_name(&bmark_name) {
+        eprintln!(
+            "WARNING: Bookmarks with spaces or special characters cannot be accesed through aliases. Added it anyway."
+        );
     }
+
+    let path = paths::portable(&cwd.display().to_string(), &bookmarks.path_maps);
+    bookmarks.insert(&bmark_name, &path)?;
+    bookmarks.mark_added(&[bmark_name]);
+    bmark_update(config)
 }
 
-impl Config {
-    fn new(_opts: &cli::Opts) -> Result<Self, String> {
-        let mut dmenu_cmd: Option<String> = None;
-        let mut editor_cmd: Option<String> = None;
-        let mut display_sep: Option<String> = None;
-        let mut terminal_cmd: Option<String> = None;
-        let mut alias_prefix: Option<String> = None;
-        let mut data_dir: Option<PathBuf> = None;
-        let mut show_paths: Option<bool> = None;
+/// Add the directories listed on stdin, all in one write. Each line (or NUL-separated record)
+/// is a path, or a name and a path separated by a tab.
+fn bmark_add_stdin(config: &Config) -> BmarkResult {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("ERROR: Could not read stdin: {e}"))?;
+    let separator = if input.contains('\0') { '\0' } else { '\n' };
 
-        // Default data_dir
-        data_dir = match ProjectDirs::from("com", "bmark", "bmark") {
-            Some(proj_dirs) => Some(PathBuf::from(proj_dirs.data_dir())),
-            None => data_dir,
+    // Named records are added first, so that derived names never take a name that was asked for
+    let mut records = vec![];
+    for record in input.split(separator) {
+        let record = record.strip_suffix('\r').unwrap_or(record);
+        if record.trim().is_empty() {
+            continue;
+        }
+        let (name, path) = match record.split_once('\t') {
+            Some((name, path)) => (Some(name.trim()), path),
+            None => (None, record),
         };
+        let dir = PathBuf::from(paths::expand(path, &[]));
+        let dir = dir
+            .canonicalize()
+            .ok()
+            .filter(|d| d.is_dir())
+            .ok_or_else(|| format!("ERROR: `{path}` is not a directory."))?;
+        records.push((name, dir));
+    }
+    records.sort_by_key(|(name, _)| name.is_none());
 
-        // Read config form toml file
-        match Self::get_user_config() {
-            Ok(toml_config) => {
-                data_dir = match toml_config.get("data_dir") {
-                    Some(toml::Value::String(p)) => Some(PathBuf::from(p)),
-                    _ => data_dir,
-                };
-                Self::try_get_string_option(&toml_config, &mut dmenu_cmd, "dmenu_cmd");
-                Self::try_get_string_option(&toml_config, &mut editor_cmd, "editor_cmd");
-                Self::try_get_string_option(&toml_config, &mut display_sep, "display_sep");
-                Self::try_get_string_option(&toml_config, &mut terminal_cmd, "terminal_cmd");
-                Self::try_get_string_option(&toml_config, &mut alias_prefix, "alias_prefix");
-                Self::try_get_bool_option(&toml_config, &mut show_paths, "show_paths");
+    config.create_data_dir()?;
+    let bookmarks = Bookmarks::from_config(config);
+    // Checked against the bookmarks while they are locked, so no other bmark process can take a
+    // name or add a path in between
+    let new = bookmarks.insert_chosen(|map| {
+        let mut bookmarked = bookmarks::bookmarked_dirs(map);
+        let mut taken: BTreeSet<String> = map.keys().cloned().collect();
+        let mut new = vec![];
+        for (name, dir) in records {
+            if let Some(existing) = bookmarked.get(&dir) {
+                eprintln!("WARNING: `{}` is already bookmarked as `{existing}`, skipping it.", dir.display());
+                continue;
             }
-            Err(e) => eprintln!("{e}"),
-        };
+            let name = match name {
+                Some("") => return Err(format!("ERROR: Empty name for `{}`.", dir.display())),
+                Some(name) if taken.contains(name) => {
+                    return Err(format!("A bookmark with the name '{name}' already exists."))
+                }
+                Some(name) => name.to_string(),
+                None => bookmarks::unique_name(&dir, &taken),
+            };
+            if !bookmarks::is_alias_name(&name) {
+                eprintln!("WARNING: `{name}` contains spaces or special characters and will have no alias.");
+            }
+            taken.insert(name.clone());
+            bookmarked.insert(dir.clone(), name.clone());
+            new.push((name, paths::portable(&dir.display().to_string(), &bookmarks.path_maps)));
+        }
+        Ok(new)
+    })?;
+    if new.is_empty() {
+        eprintln!("Nothing to add.");
+        return Ok(());
+    }
 
-        let mut config = Self::default();
+    let names: Vec<String> = new.into_iter().map(|(name, _)| name).collect();
+    bookmarks.mark_added(&names);
+    eprintln!("Added {} bookmark{}.", names.len(), if names.len() == 1 { "" } else { "s" });
+    bmark_update(config)
+}
 
-        if let Some(o) = dmenu_cmd {
-            config.dmenu_cmd = o;
-        }
-        if let Some(o) = editor_cmd {
-            config.editor_cmd = o;
-        }
-        if let Some(o) = data_dir {
-            config.data_dir = PathBuf::from(o);
-        }
-        if let Some(o) = display_sep {
-            config.display_sep = o;
-        }
-        if let Some(o) = terminal_cmd {
-            config.terminal_cmd = o;
-        }
-        if let Some(o) = alias_prefix {
-            config.alias_prefix = o;
-        }
-        if let Some(o) = show_paths {
-            config.show_paths = o;
-        }
+/// Find project directories and bookmark the ones that are not bookmarked yet.
+fn bmark_scan(config: &Config, scan_opts: &cli::ScanOpts) -> BmarkResult {
+    let root = PathBuf::from(&scan_opts.root);
+    let root = root
+        .canonicalize()
+        .map_err(|e| format!("ERROR: Cannot scan `{}`: {e}", root.display()))?;
+    let markers = scan::Markers {
+        git: scan_opts.git || scan_opts.marker.is_empty(),
+        files: scan_opts.marker.clone(),
+    };
+    let projects = scan::find_projects(&root, scan_opts.depth.unwrap_or(3), &markers);
+
+    let bookmarks = Bookmarks::from_config(config);
+    let map = bookmarks.try_get_map()?;
+    let bookmarked = bookmarks::bookmarked_dirs(&map);
+    let mut taken: BTreeSet<String> = map.keys().cloned().collect();
 
-        if !config.data_dir.is_dir() {
-            return Err("ERROR: Could not deternine data directory.".to_string());
+    let mut new = vec![];
+    for dir in &projects {
+        if bookmarked.contains_key(dir) {
+            continue;
         }
+        let name = bookmarks::unique_name(dir, &taken);
+        taken.insert(name.clone());
+        new.push((name, dir.display().to_string()));
+    }
 
-        Ok(config)
+    let skipped = projects.len() - new.len();
+    eprintln!(
+        "Found {} project{}{}.",
+        projects.len(),
+        if projects.len() == 1 { "" } else { "s" },
+        if skipped > 0 { format!(", {skipped} already bookmarked") } else { String::new() }
+    );
+    if new.is_empty() || !confirm_new(config, &new, scan_opts.dry_run, scan_opts.yes)? {
+        return Ok(());
     }
 
-    fn try_get_string_option(
-        config: &HashMap<String, toml::Value>,
-        field: &mut Option<String>,
-        option: &str,
-    ) {
-        if let Some(toml::Value::String(s)) = config.get(option) {
-            *field = Some(s.clone());
-        }
+    config.create_data_dir()?;
+    let portable: Vec<(String, String)> = new
+        .into_iter()
+        .map(|(name, path)| (name, paths::portable(&path, &bookmarks.path_maps)))
+        .collect();
+    bookmarks.insert_many(&portable)?;
+    bookmarks.mark_added(&portable.into_iter().map(|(name, _)| name).collect::<Vec<_>>());
+    bmark_update(config)
+}
+
+/// Show bookmarks that are about to be added, and ask whether to add them unless `yes` is given.
+/// Returns whether they should be added.
+fn confirm_new(config: &Config, new: &[(String, String)], dry_run: bool, yes: bool) -> Result<bool, String> {
+    let preview: Vec<Bookmark> = new
+        .iter()
+        .map(|(name, path)| Bookmark::new(name.clone(), path.clone()))
+        .collect();
+    print!("{}", Bookmarks::table(&preview, &config.display_sep, &Style::stdout()));
+    if dry_run {
+        return Ok(false);
     }
 
-    fn try_get_bool_option(
-        config: &HashMap<String, toml::Value>,
-        field: &mut Option<bool>,
-        option: &str,
-    ) {
-        if let Some(toml::Value::Boolean(b)) = config.get(option) {
-            *field = Some(b.clone());
-        }
+    if !yes && !output::confirm(&format!("Add {} bookmarks? [y/N] ", new.len()), "add")? {
+        return Err("Nothing was added.".to_string());
     }
+    Ok(true)
+}
 
-    fn user_config_file() -> Result<PathBuf, String> {
-        match ProjectDirs::from("com", "bmark", "bmark") {
-            Some(proj_dirs) => Ok(PathBuf::from(proj_dirs.config_dir()).join("config.toml")),
-            None => return Err(format!("ERROR: could not determine config directory")),
+/// Bookmark the directories known to another program.
+fn bmark_import(config: &Config, import_opts: &cli::ImportOpts) -> BmarkResult {
+    let source = import_opts.from.ok_or(
+        "ERROR: Give the program to import from with --from: gtk, bashmarks, autojump or zoxide.",
+    )?;
+    if !source.is_weighted() && (import_opts.top.is_some() || import_opts.min_score.is_some()) {
+        return Err("ERROR: --top and --min-score can only be used with autojump and zoxide.".to_string());
+    }
+    let raw = match (import_opts.file.as_deref().map(PathBuf::from), source.default_file()) {
+        (Some(file), _) if file == Path::new("-") => {
+            let mut raw = String::new();
+            io::stdin()
+                .read_to_string(&mut raw)
+                .map_err(|e| format!("ERROR: Could not read stdin: {e}"))?;
+            raw
         }
+        (Some(file), _) | (None, Some(file)) => fs::read_to_string(&file)
+            .map_err(|e| format!("ERROR: Could not read `{}`: {e}", file.display()))?,
+        (None, None) => return Err("ERROR: Give the file to import, or - to read it from stdin.".to_string()),
+    };
+
+    let found = import::parse(source, &raw)?;
+    let total = found.len();
+    // Exports of bmark keep their paths as they are, even when they do not exist on this machine
+    let mut found: Vec<(import::Imported, PathBuf)> = found
+        .into_iter()
+        .filter_map(|f| {
+            let expanded = PathBuf::from(paths::expand(&f.path, &[]));
+            match expanded.canonicalize().ok().filter(|d| d.is_dir()) {
+                Some(dir) => Some((f, dir)),
+                None if source.is_exact() => Some((f, expanded)),
+                None => None,
+            }
+        })
+        .collect();
+    let missing = total - found.len();
+    if let Some(min) = import_opts.min_score {
+        found.retain(|(f, _)| f.score.is_some_and(|s| s >= min));
+    }
+    if source.is_weighted() {
+        found.sort_by(|(a, _), (b, _)| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
     }
+    if let Some(top) = import_opts.top {
+        found.truncate(top);
+    }
+
+    let bookmarks = Bookmarks::from_config(config);
+    let map = bookmarks.try_get_map()?;
+    let mut bookmarked: BTreeSet<PathBuf> = bookmarks::bookmarked_dirs(&map).into_keys().collect();
+    let mut taken: BTreeSet<String> = map.keys().cloned().collect();
+    // Paths with references cannot be compared once resolved, so exports are compared as stored
+    let stored: BTreeSet<String> = bookmarks
+        .try_get_entries()?
+        .values()
+        .map(|e| e.path().clone())
+        .collect();
 
-    fn get_user_config() -> Result<HashMap<String, toml::Value>, String> {
-        let config_file = Config::user_config_file()?;
-        let mut m: HashMap<String, toml::Value> = Default::default();
-        match File::open(&config_file) {
-            Ok(mut file) => {
-                let mut lines = String::new();
-                if let Err(_) = file.read_to_string(&mut lines) {
-                    return Err(format!(
-                        "ERROR: Can not read config file: `{}`",
-                        config_file.display()
-                    ));
+    let conflict = import_opts.conflict.unwrap_or_default();
+    let mut new: Vec<(String, Entry)> = vec![];
+    let mut replaced = vec![];
+    let mut skipped = 0;
+    for (f, dir) in found {
+        if (source.is_exact() && stored.contains(&f.path)) || !bookmarked.insert(dir.clone()) {
+            skipped += 1;
+            continue;
+        }
+        let entry = match source.is_exact() {
+            true => f.entry(),
+            false => Entry::Path(paths::portable(&dir.display().to_string(), &bookmarks.path_maps)),
+        };
+        let name = match f.name {
+            None => bookmarks::unique_name(&dir, &taken),
+            Some(name) if !taken.contains(&name) => name,
+            Some(name) => match conflict {
+                import::Conflict::Rename => (2..)
+                    .map(|n| format!("{name}-{n}"))
+                    .find(|c| !taken.contains(c))
+                    .expect("there are infinitely many candidates"),
+                import::Conflict::Skip => {
+                    eprintln!("WARNING: The name `{name}` is taken, skipping `{}`.", dir.display());
+                    continue;
                 }
-                let user_config: HashMap<String, toml::Value> =
-                    toml::from_str(lines.as_str()).unwrap();
-                for (k, v) in user_config.iter() {
-                    m.insert(k.to_owned(), v.to_owned());
+                import::Conflict::Replace => {
+                    new.retain(|(n, _)| *n != name);
+                    if map.contains_key(&name) && !replaced.contains(&name) {
+                        replaced.push(name.clone());
+                    }
+                    name
                 }
-                Ok(m)
-            }
-            Err(_) => Ok(m),
-        }
+            },
+        };
+        taken.insert(name.clone());
+        new.push((name, entry));
     }
 
-    fn get_bookmarks_file(&self) -> PathBuf {
-        let mut bookmarks_file = PathBuf::from(&self.data_dir);
-        bookmarks_file.push(BOOKMARKS_FILE);
-        bookmarks_file
+    let plural = |n: usize| if n == 1 { "y" } else { "ies" };
+    eprint!("Found {} director{}", total, plural(total));
+    if missing > 0 {
+        eprint!(", {missing} missing");
     }
-
-    fn get_alias_file(&self) -> PathBuf {
-        let mut bookmarks_file = PathBuf::from(&self.data_dir);
-        bookmarks_file.push(ALIAS_FILE);
-        bookmarks_file
+    if skipped > 0 {
+        eprint!(", {skipped} already bookmarked");
     }
-}
-
-impl fmt::Display for Config {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "data_dir = \"{}\"
-dmenu_cmd = \"{}\"
-editor_cmd = \"{}\"
-terminal_cmd = \"{}\"
-alias_prefix = \"{}\"
-display_sep = \"{}\",
-show_paths = \"{}\"",
-            self.data_dir.display(),
-            self.dmenu_cmd,
-            self.editor_cmd,
-            self.terminal_cmd,
-            self.alias_prefix,
-            self.display_sep,
-            self.show_paths,
-        )
+    eprintln!(".");
+    if !replaced.is_empty() {
+        eprintln!("Replacing: {}", replaced.join(", "));
+    }
+    let no_alias: Vec<&str> = new
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| !bookmarks::is_alias_name(name))
+        .collect();
+    if !no_alias.is_empty() {
+        eprintln!(
+            "WARNING: Names with spaces or special characters will have no alias: {}",
+            no_alias.join(", ")
+        );
+    }
+    let preview: Vec<(String, String)> = new.iter().map(|(n, e)| (n.clone(), e.path().clone())).collect();
+    if new.is_empty() || !confirm_new(config, &preview, import_opts.dry_run, import_opts.yes)? {
+        return Ok(());
     }
+
+    config.create_data_dir()?;
+    bookmarks.set_many(&new)?;
+    bookmarks.mark_added(&new.into_iter().map(|(name, _)| name).collect::<Vec<_>>());
+    bmark_update(config)
 }
 
-// Add: source_cmd subcommand to output the command to source the alias file
-fn bmark_config(config: &Config, subcommand: cli::ConfigCommand) -> BmarkResult {
-    match subcommand {
-        cli::ConfigCommand::Show(_) => println!("{config}"),
-        cli::ConfigCommand::Create(_) => {
-            let config_file = Config::user_config_file()?;
-            if config_file.exists() {
-                eprintln!("ERROR: Cannot create default config file, a config file already exists at `{}`.", 
-                          config_file.display());
-                exit(1);
-            }
-            let config = Config::default();
-            fs::create_dir_all(config_file.parent().expect("No parrent of config file."))
-                .expect("Could not create config directory.");
-            match OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&config_file)
-            {
-                Ok(mut file) => {
-                    let buf = format!("data_dir = \"{}\"\ndmenu_cmd = \"{}\"\neditor_cmd = \"{}\"\nterminal_cmd = \"{}\"\nalias_prefix = \"{}\"\ndisplay_sep = \"{}\"", 
-                        config.data_dir.display(),
-                        config.dmenu_cmd,
-                        config.editor_cmd,
-                        config.terminal_cmd,
-                        config.alias_prefix,
-                        config.display_sep,
-                    );
-
-                    if let Err(e) = file.write_all(buf.as_bytes()) {
-                        eprintln!("ERROR: Could not write to config file : {e}");
-                    }
-                }
-                Err(_) => {
-                    eprintln!(
-                        "ERROR: Could not open config file: `{}`",
-                        config_file.display()
-                    );
-                    exit(1);
-                }
+/// Write the bookmarks into a GTK bookmarks file, keeping the entries that bmark did not write.
+fn export_gtk(config: &Config, bookmarks: &Bookmarks, file: &Path) -> Result<usize, String> {
+    let existing = fs::read_to_string(file).unwrap_or_default();
+    let target = std::path::absolute(file).unwrap_or(file.to_path_buf());
+    // Every line is an entry that bmark wrote followed by the file it wrote it to, so that exports
+    // to other files never take over the entries of the user. Lines without a file are from the
+    // default file.
+    let state = config.data_dir.join(GTK_EXPORTED_FILE);
+    let mut previous = BTreeSet::new();
+    let mut others = vec![];
+    for line in fs::read_to_string(&state).unwrap_or_default().lines() {
+        let (uri, owner) = match line.split_once(' ') {
+            Some((uri, owner)) => (uri, Some(PathBuf::from(owner))),
+            None => (line, import::Source::Gtk.default_file()),
+        };
+        match owner {
+            Some(owner) if owner == target => {
+                previous.insert(uri.to_string());
             }
+            Some(owner) => others.push(format!("{uri} {}\n", owner.display())),
+            None => {}
         }
-        cli::ConfigCommand::Edit(_) => {
-            let path = Config::user_config_file()?;
+    }
 
-            if !path.exists() {
-                fs::create_dir_all(path.parent().unwrap()).unwrap();
-            }
-            let editor_cmd = config.editor_cmd.clone() + " " + path.to_str().unwrap();
-            Command::new("sh")
-                .arg("-c")
-                .arg(editor_cmd)
-                .status()
-                .expect("ERROR: Failed to execute editor command.");
-        }
-        cli::ConfigCommand::SourceCmd(_) => {
-            println!("source \"{}/{}\"", config.data_dir.display(), ALIAS_FILE,);
-            exit(0);
+    let entries = bookmarks.entries();
+    let (contents, owned) = export::gtk(&entries, &existing, &previous);
+    if contents != existing {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("ERROR: Could not create `{}`: {e}", dir.display()))?;
         }
+        bookmarks::write_atomic(file, &contents)?;
     }
-
-    Ok(())
+    let exported: String = others
+        .into_iter()
+        .chain(owned.iter().map(|uri| format!("{uri} {}\n", target.display())))
+        .collect();
+    fs::write(&state, exported)
+        .map_err(|e| format!("ERROR: Could not write `{}`: {e}", state.display()))?;
+    config.log(format!("wrote `{}`", file.display()));
+    Ok(entries.len())
 }
 
-// Make sure that there are no duplicates
-fn bmark_add(config: &Config, name: Option<String>) -> BmarkResult {
-    let bookmarks = Bookmarks::from_config(config);
-    let bookmarks_file = bookmarks.file.clone();
-
-    let data_dir = bookmarks_file
-        .parent()
-        .expect("There should always be a parrent in the path.");
-
-    if !data_dir.exists() {
-        if let Err(e) = fs::create_dir_all(data_dir) {
-            return Err(format!("ERROR: Could not create data directory: `{}`", e));
-        };
-    }
-
-    let cwd = env::current_dir().unwrap();
-    let mut bmark_name = match name {
-        Some(n) => n,
-        None => cwd.file_stem().unwrap().to_str().unwrap().to_string(),
+/// Write a launcher for every bookmark into `dir`, and remove the launchers of bookmarks that
+/// are gone. Files that bmark did not write are neither overwritten nor removed.
+fn export_desktop(config: &Config, bookmarks: &Bookmarks, dir: &Path) -> Result<usize, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("ERROR: Could not create `{}`: {e}", dir.display()))?;
+    let dir = &dir.canonicalize().unwrap_or(dir.to_path_buf());
+    let state = config.data_dir.join(DESKTOP_EXPORTED_FILE);
+    let previous: BTreeSet<PathBuf> = fs::read_to_string(&state)
+        .unwrap_or_default()
+        .lines()
+        .map(PathBuf::from)
+        .collect();
+    let available = |file: &PathBuf, written: &BTreeSet<PathBuf>| {
+        !written.contains(file) && (previous.contains(file) || fs::symlink_metadata(file).is_err())
     };
 
-    if bookmarks.get_map().contains_key(&bmark_name) {
-        return Err(format!(
-            "A bookmark with the name '{bmark_name}' already exists."
-        ));
+    let entries = bookmarks.entries();
+    let mut written = BTreeSet::new();
+    for b in &entries {
+        let file = std::iter::once(export::desktop_file_name(&b.name))
+            .chain((2..).map(|n| export::desktop_file_name(&format!("{}-{n}", b.name))))
+            .map(|name| dir.join(name))
+            .find(|file| available(file, &written))
+            .expect("there are infinitely many candidates");
+        let command = format!("{} {}", config.terminal_cmd, shell_quote(&b.path));
+        let contents = export::desktop_entry(b, &command);
+        if fs::read_to_string(&file).ok().as_ref() != Some(&contents) {
+            bookmarks::write_atomic(&file, &contents)?;
+        }
+        written.insert(file);
     }
 
-    if bmark_name.rfind(' ') != None {
-        eprintln!(
-            "WARNING: Bookmarks with spaces cannot be accesed through aliases. Added it anyway."
-        );
-        bmark_name = "\"".to_string() + bmark_name.as_str() + "\"";
+    // Launchers written into other directories are left for exports to those directories
+    let (others, stale): (Vec<PathBuf>, Vec<PathBuf>) = previous
+        .into_iter()
+        .filter(|file| !written.contains(file))
+        .partition(|file| file.parent() != Some(dir));
+    for file in stale {
+        if let Err(e) = fs::remove_file(&file) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(format!("ERROR: Could not remove `{}`: {e}", file.display()));
+            }
+        }
     }
+    let exported: String = others
+        .iter()
+        .chain(&written)
+        .map(|file| format!("{}\n", file.display()))
+        .collect();
+    fs::write(&state, exported)
+        .map_err(|e| format!("ERROR: Could not write `{}`: {e}", state.display()))?;
+    config.log(format!("updated launchers in `{}`", dir.display()));
+    Ok(entries.len())
+}
 
-    match OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&bookmarks_file)
-    {
-        Ok(mut file) => {
-            let cwd = env::current_dir().unwrap();
-            if let Err(_) = writeln!(file, "{} = \"{}\"", bmark_name, cwd.display()) {
+fn bmark_export(config: &Config, export_opts: &cli::ExportOpts) -> BmarkResult {
+    let target = export_opts
+        .to
+        .ok_or("ERROR: Give the format to export to with --to: gtk, html, json, csv or bashmarks.")?;
+    let bookmarks = Bookmarks::from_config(config);
+    let output = export_opts.output.as_ref().map(PathBuf::from);
+
+    let contents = match target {
+        Target::Gtk => {
+            let file = output
+                .or_else(|| import::Source::Gtk.default_file())
+                .ok_or("ERROR: Could not determine the GTK bookmarks file, give it with --output.")?;
+            let count = export_gtk(config, &bookmarks, &file)?;
+            eprintln!("Exported {count} bookmarks to `{}`.", file.display());
+            return Ok(());
+        }
+        Target::Desktop => {
+            let dir = output
+                .or_else(export::desktop_dir)
+                .ok_or("ERROR: Could not determine the applications directory, give it with --output.")?;
+            let count = export_desktop(config, &bookmarks, &dir)?;
+            eprintln!("Exported {count} launchers to `{}`.", dir.display());
+            return Ok(());
+        }
+        Target::Html => export::html(&bookmarks.entries()),
+        Target::Json => export::records(&bookmarks.try_get_entries()?).render(Format::Json),
+        Target::Csv => export::records(&bookmarks.try_get_entries()?).render(Format::Csv),
+        Target::Bashmarks => {
+            let entries = bookmarks.entries();
+            let (contents, skipped) = export::bashmarks(&entries);
+            if !skipped.is_empty() {
                 eprintln!(
-                    "ERROR: Could not write to file: {}",
-                    bookmarks_file.display()
+                    "WARNING: Bashmarks names can only contain letters, digits and `_`, skipped: {}",
+                    skipped.join(", ")
                 );
-                exit(1);
             }
+            contents
         }
-        Err(_) => {
-            eprintln!(
-                "ERROR: Could not open bookmarks file: `{}`",
-                bookmarks_file.display()
-            );
-            exit(1);
+    };
+    match output {
+        Some(file) => bookmarks::write_atomic(&file, &contents),
+        None => {
+            print!("{contents}");
+            Ok(())
         }
     }
-    bmark_update(config)
 }
 
+/// Edit a copy of the bookmarks file, and only replace the real file once the copy is valid.
 fn bmark_edit(config: &Config) -> BmarkResult {
     let path = config.get_bookmarks_file();
-    let editor_cmd = config.editor_cmd.clone() + " " + path.to_str().unwrap();
-    if let Err(e) = Command::new("sh").arg("-c").arg(editor_cmd).status() {
-        return Err(format!("ERROR: Failed to execute editor command:\n{e}"));
+    let original = fs::read_to_string(&path).unwrap_or_default();
+    let tmp = path.with_file_name(".bookmarks.edit.toml");
+    if let Err(e) = fs::write(&tmp, &original) {
+        return Err(format!("ERROR: Could not create temporary file `{}`: {e}", tmp.display()));
     }
-    bmark_update(config)
-}
 
-fn bmark_list(config: &mut Config) -> BmarkResult {
-    config.show_paths = true;
-    let bookmarks = Bookmarks::from_config(&config);
-    print!("{}", bookmarks.readable_with_paths(&config.display_sep));
-    Ok(())
+    let editor_cmd = config.editor_cmd.clone() + " \"" + tmp.to_str().unwrap() + "\"";
+    let edited = loop {
+        config.log(format!("running `{editor_cmd}`"));
+        match Command::new("sh").arg("-c").arg(&editor_cmd).status() {
+            Ok(status) if status.success() => {}
+            // An editor exits with an error to abort, like `:cq` in vim
+            Ok(status) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(format!(
+                    "ERROR: Editor command failed ({status}), discarded changes to bookmarks."
+                ));
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(format!("ERROR: Failed to execute editor command:\n{e}"));
+            }
+        }
+        let edited = fs::read_to_string(&tmp).unwrap_or_default();
+        match bookmarks::validate(&edited, &config.host_path_maps()) {
+            Ok(warnings) => {
+                for w in warnings {
+                    eprintln!("{w}");
+                }
+                break edited;
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                let answer = output::ask("Bookmarks file is invalid. (e)dit again or (d)iscard changes? [e/d] ");
+                match answer.as_deref().map(str::trim) {
+                    Some("e") | Some("") => continue,
+                    _ => {
+                        let _ = fs::remove_file(&tmp);
+                        return Err("ERROR: Discarded invalid changes to bookmarks.".to_string());
+                    }
+                }
+            }
+        }
+    };
+
+    let _ = fs::remove_file(&tmp);
+    if edited == original {
+        config.log("bookmarks file unchanged".to_string());
+        return Ok(());
+    }
+    Bookmarks::from_config(config).write_raw(&edited)?;
+    bmark_update(config)
 }
 
-// TODO: Check that dmenu-like program is executable
-// TODO: Support fzf
-fn bmark_open(config: &mut Config, open_opts: &cli::OpenOpts) -> BmarkResult {
+fn bmark_list(config: &Config, list_opts: &cli::ListOpts, format: Format) -> BmarkResult {
+    if list_opts.missing && list_opts.existing {
+        return Err("ERROR: `--missing` and `--existing` cannot be used together.".to_string());
+    }
+    let query = Query {
+        pattern: list_opts.query.clone(),
+        under: list_opts.under.as_ref().map(PathBuf::from),
+        exists: match (list_opts.missing, list_opts.existing) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None,
+        },
+        tag: list_opts.tag.clone(),
+        sort: list_opts.sort,
+        reverse: list_opts.reverse,
+    };
 
-    // Override config with cli option, if any were specified.
-    if open_opts.show_paths {
-        config.show_paths = true
+    let bookmarks = Bookmarks::from_config(config);
+    let entries = bookmarks.query(&query);
+    if list_opts.tree {
+        if format != Format::Human {
+            return Err("ERROR: `--tree` can only be used with the human output format.".to_string());
+        }
+        let cwd = env::current_dir().unwrap();
+        let current = bookmarks.containing(&cwd).into_iter().next().map(|(k, _)| k);
+        print!("{}", tree::render(&entries, current.as_deref(), &Style::stdout()));
+        return Ok(());
     }
-    if let Some(cmd) = &open_opts.cmd {
-        config.dmenu_cmd = cmd.clone()
+    if format == Format::Human {
+        print!("{}", Bookmarks::table(&entries, &config.display_sep, &Style::stdout()));
+        return Ok(());
     }
-    if let Some(term) = &open_opts.terminal {
-        config.terminal_cmd = term.clone()
+    let mut records = Records::new(&["name", "path", "tags", "exists", "added", "used"]);
+    for b in entries {
+        let exists = b.exists();
+        records.push(vec![
+            Value::from(b.name),
+            Value::from(b.path),
+            Value::from(b.tags),
+            Value::from(exists),
+            Value::from(b.added),
+            Value::from(b.used),
+        ]);
     }
+    print!("{}", records.render(format));
+    Ok(())
+}
 
-    let bookmarks = Bookmarks::from_config(&config);
+// TODO: Check that dmenu-like program is executable
+// TODO: Support fzf
+fn bmark_open(config: &Config) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
     let bookmarls_str = if config.show_paths {
         bookmarks.readable_with_paths(&config.display_sep)
     }
@@ -442,21 +660,22 @@ fn bmark_open(config: &mut Config, open_opts: &cli::OpenOpts) -> BmarkResult {
         + "'"
         + " | "
         + config.dmenu_cmd.as_str();
-    let mut path = match Command::new("sh").arg("-c").arg(&cmd).output() {
+    config.log(format!("running `{}`", config.dmenu_cmd));
+    let (name, mut path) = match Command::new("sh").arg("-c").arg(&cmd).output() {
         Ok(output) => {
             let mut choice = String::from_utf8(output.stdout).unwrap();
-            if choice == "" {
+            if choice.is_empty() {
                 eprintln!("No bookmark chosen.");
                 exit(1);
             }
             let sep = config.display_sep.clone();
 
             match choice.split_once(&sep) {
-                Some((_, p)) => p.to_owned(),
+                Some((n, p)) => (n.trim_end().to_owned(), p.to_owned()),
                 None => {
-                    if choice.chars().last() == Some('\n') { choice.pop(); }
-                    match bookmarks.get_map().get(&choice) {
-                        Some(c) => c.clone(),
+                    if choice.ends_with('\n') { choice.pop(); }
+                    match bookmarks.entries().into_iter().find(|b| b.name == choice) {
+                        Some(b) => (b.name, b.path),
                         None => return Err(format!("ERROR: Could not find bookmark with the name: {}.", choice)),
                     }
                 },
@@ -467,64 +686,451 @@ fn bmark_open(config: &mut Config, open_opts: &cli::OpenOpts) -> BmarkResult {
             exit(1);
         }
     };
-    if path.chars().last() == Some('\n') { path.pop(); }
+    if path.ends_with('\n') { path.pop(); }
 
     let cmd = config.terminal_cmd.clone() + " \"" + path.as_str() + "\"";
+    config.log(format!("running `{cmd}`"));
 
-    if let Err(_) = Command::new("sh").arg("-c").arg(&cmd).status() {
+    if Command::new("sh").arg("-c").arg(&cmd).status().is_err() {
         eprintln!(
             "ERROR: Could not open terminal with this command: `{}`",
             cmd
         );
         exit(1);
     }
+    bookmarks.mark_used(&name);
 
     Ok(())
 }
 
-fn bmark_rm(config: &Config, bmark: String) -> BmarkResult {
-    let bookmarks = Bookmarks::from_config(&config);
-    let mut bookmarks_str = String::new();
-    let mut removed = false;
+fn print_records(records: &[String], null: bool) {
+    let term = if null { '\0' } else { '\n' };
+    let mut out = String::new();
+    for r in records {
+        out.push_str(r);
+        out.push(term);
+    }
+    print!("{out}");
+}
 
-    for (mut k, v) in bookmarks.get_map() {
-        if k == bmark {
-            removed = true;
-            continue;
+fn bmark_path(config: &Config, path_opts: &cli::PathOpts, format: Format) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
+    let (name, path) = bookmarks.lookup_path(&path_opts.name)?;
+    bookmarks.mark_used(&name);
+    if format == Format::Human {
+        print_records(&[path], path_opts.null);
+    } else {
+        let mut records = Records::new(&["name", "path"]);
+        records.push(vec![Value::from(name), Value::from(path)]);
+        print!("{}", records.render_single(format));
+    }
+    Ok(())
+}
+
+fn bmark_which(config: &Config, which_opts: &cli::WhichOpts, format: Format) -> BmarkResult {
+    let dir = match &which_opts.dir {
+        Some(d) => PathBuf::from(d),
+        None => env::current_dir().unwrap(),
+    };
+    let bookmarks = Bookmarks::from_config(config);
+    let found = bookmarks.containing(&dir);
+    if found.is_empty() {
+        return Err(format!(
+            "ERROR: no bookmark contains `{}`.",
+            dir.display()
+        ));
+    }
+    if format == Format::Human {
+        let names: Vec<String> = found.into_iter().map(|(k, _)| k).collect();
+        print_records(&names, which_opts.null);
+    } else {
+        let mut records = Records::new(&["name", "path"]);
+        for (name, path) in found {
+            records.push(vec![Value::from(name), Value::from(path)]);
         }
-        if k.rfind(' ') != None {
-            k = "\"".to_string() + k.as_str() + "\""
+        print!("{}", records.render(format));
+    }
+    Ok(())
+}
+
+/// Let the user choose bookmarks with the dmenu-like command. Every line of its output is a
+/// choice, so a picker that supports selecting multiple lines can choose several bookmarks. Glob
+/// bookmarks are shown with their pattern rather than their matches, which are not bookmarks of
+/// their own.
+fn pick(config: &Config, bookmarks: &Bookmarks) -> Result<Vec<String>, String> {
+    let stored: Vec<Bookmark> = bookmarks
+        .try_get_map()?
+        .into_iter()
+        .map(|(name, path)| Bookmark::new(name, path))
+        .collect();
+    let menu = if config.show_paths {
+        Bookmarks::table(&stored, &config.display_sep, &Style::default())
+    } else {
+        stored.iter().map(|b| b.name.clone() + "\n").collect()
+    };
+    config.log(format!("running `{}`", config.dmenu_cmd));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&config.dmenu_cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| format!("ERROR: Error running dmenu-command: `{}`", config.dmenu_cmd))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(menu.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|_| format!("ERROR: Error running dmenu-command: `{}`", config.dmenu_cmd))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| match l.split_once(&config.display_sep) {
+            Some((name, _)) => name.trim_end().to_string(),
+            None => l.to_string(),
+        })
+        .collect())
+}
+
+fn bmark_rm(config: &Config, rm_opts: &cli::RmOpts) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
+    let mut names: Vec<String> = rm_opts.name.iter().chain(&rm_opts.names).cloned().collect();
+    if rm_opts.missing {
+        let missing = bookmarks.query(&Query {
+            exists: Some(false),
+            ..Query::default()
+        });
+        if missing.is_empty() && names.is_empty() && !rm_opts.pick {
+            println!("No bookmarks point to missing directories.");
+            return Ok(());
         }
-        bookmarks_str += format!("{} = \"{}\"\n", k, v).as_str();
+        names.extend(missing.into_iter().map(|b| b.name));
     }
-    if !removed {
-        return Err(format!("ERROR: could not find bookmark `{}`.", bmark));
+    if rm_opts.pick {
+        names.extend(pick(config, &bookmarks)?);
     }
-    let bytes = bookmarks_str.as_bytes();
-    match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(config.get_bookmarks_file())
-    {
-        Ok(mut file) => match file.write_all(bytes) {
-            Ok(_) => {}
-            Err(_) => return Err("ERROR: Could not open bookmarks file".to_string()),
-        },
-        Err(_) => return Err("ERROR: Could not open bookmarks file".to_string()),
+    let mut seen = BTreeSet::new();
+    names.retain(|n| seen.insert(n.clone()));
+    if names.is_empty() {
+        return Err("ERROR: No bookmarks to remove. Give their names, or use --pick or --missing.".to_string());
+    }
+
+    let map = bookmarks.get_map();
+    if let Some(name) = names.iter().find(|n| !map.contains_key(*n)) {
+        return Err(bookmarks::not_found(name, map.keys().map(String::as_str)));
+    }
+
+    if !rm_opts.yes {
+        eprintln!("Removing {} bookmark{}:", names.len(), if names.len() == 1 { "" } else { "s" });
+        let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+        for name in &names {
+            eprintln!("    {name:<width$}{}{}", config.display_sep, map[name]);
+        }
+        if !output::confirm("Continue? [y/N] ", "remove")? {
+            return Err("Nothing was removed.".to_string());
+        }
+    }
+
+    bookmarks.remove(&names)?;
+    bmark_update(config)
+}
+
+fn bmark_rename(config: &Config, rename_opts: &cli::RenameOpts) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
+    bookmarks.rename(&rename_opts.old, &rename_opts.new)?;
+    bmark_update(config)
+}
+
+fn bmark_history(config: &Config, format: Format) -> BmarkResult {
+    let file = config.get_bookmarks_file();
+    let snapshots = history::list(&file);
+    let mut after = fs::read_to_string(&file).unwrap_or_default();
+
+    let mut records = Records::new(&["snapshot", "id", "age", "changes"]);
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let before = snapshot.contents();
+        let changes = history::changes(&before, &after);
+        records.push(vec![
+            Value::from(i + 1),
+            Value::from(snapshot.id.to_string()),
+            Value::from(snapshot.age()),
+            Value::from(changes),
+        ]);
+        after = before;
+    }
+
+    if format != Format::Human {
+        print!("{}", records.render(format));
+        return Ok(());
+    }
+    if snapshots.is_empty() {
+        println!("No snapshots yet.");
+    }
+    for row in records.rows() {
+        let changes: Vec<&str> = row[3]
+            .as_array()
+            .map(|a| a.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        println!(
+            "{:>3}  {:<16} {}",
+            row[0],
+            row[2].as_str().unwrap_or_default(),
+            changes.join(" ")
+        );
     }
     Ok(())
 }
 
-fn bmark_update(config: &Config) -> BmarkResult {
-    let bookmarks = Bookmarks::from(config.get_bookmarks_file());
-    let mut aliases = String::new();
-    for (name, path) in bookmarks.get_map() {
-        if name.rfind(' ') != None {
+/// Go back to the newest snapshot. The snapshot is used up, so undoing repeatedly walks further
+/// back in history.
+fn bmark_undo(config: &Config) -> BmarkResult {
+    let file = config.get_bookmarks_file();
+    let snapshot = match history::list(&file).into_iter().next() {
+        Some(s) => s,
+        None => return Err("ERROR: There is nothing to undo.".to_string()),
+    };
+    let current = fs::read_to_string(&file).unwrap_or_default();
+    let contents = snapshot.contents();
+    Bookmarks::from(file).replace(&contents)?;
+    let _ = fs::remove_file(&snapshot.file);
+    let changes = history::changes(&current, &contents);
+    if !changes.is_empty() {
+        println!("Undo: {}", changes.join(" "));
+    }
+    bmark_update(config)
+}
+
+/// Restore a snapshot. The current bookmarks are saved as a snapshot first, so this can be undone.
+fn bmark_restore(config: &Config, restore_opts: &cli::RestoreOpts) -> BmarkResult {
+    let file = config.get_bookmarks_file();
+    let snapshot = history::find(&file, &restore_opts.snapshot)?;
+    Bookmarks::from_config(config).write_raw(&snapshot.contents())?;
+    bmark_update(config)
+}
+
+/// Set up syncing with `sync init`, or pull and push the bookmarks.
+fn bmark_sync(config: &Config, subcommand: Option<cli::SyncCommand>) -> BmarkResult {
+    let summary = match subcommand {
+        Some(cli::SyncCommand::Init(init_opts)) => {
+            config.create_data_dir()?;
+            sync::init(&config.data_dir, &init_opts.remote)?
+        }
+        None => sync::sync(&config.data_dir)?,
+    };
+    print!("{summary}");
+    bmark_update(config)
+}
+
+/// Quote a string for POSIX shells.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// The name of the environment variable for a bookmark, like `BM_MY_PROJ` for `my-proj`.
+fn var_name(prefix: &str, name: &str) -> String {
+    let name: String = format!("{prefix}{name}")
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{name}"),
+        false => name,
+    }
+}
+
+/// Whether a bookmark can get a symlink in the links directory.
+#[cfg(unix)]
+fn is_link_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+/// The contents of the aliases file. Every bookmark gets a shell function that changes to its
+/// directory, or to a subdirectory given as argument, with completion of the subdirectories in
+/// bash and zsh. Optionally, bookmarks are exported as environment variables and the links
+/// directory is added to `CDPATH`.
+fn aliases(config: &Config, bookmarks: &BTreeMap<String, String>) -> String {
+    let mut extra = String::new();
+    if config.export_vars {
+        let mut exported = vec![];
+        for (name, path) in bookmarks {
+            // The first bookmark wins when names differ only in characters that are replaced
+            let var = var_name(&config.export_prefix, name);
+            if !exported.contains(&var) {
+                extra += format!("export {var}={}\n", shell_quote(path)).as_str();
+                exported.push(var);
+            }
+        }
+    }
+    if config.cdpath {
+        let links = shell_quote(&config.get_links_dir().display().to_string());
+        extra += format!(
+            "case \":${{CDPATH:-}}:\" in\n    *:{links}:*) ;;\n    *) export CDPATH=\"${{CDPATH:-.}}:\"{links} ;;\nesac\n"
+        )
+        .as_str();
+    }
+
+    let mut functions = String::new();
+    let mut roots = String::new();
+    let mut names = vec![];
+    let mut has_aliases = false;
+    for (name, path) in bookmarks {
+        let function = format!("{}{}", config.alias_prefix, name);
+        if !bookmarks::is_alias_name(&function) {
             continue;
-        } // Skip bookmark names with spaces
-        aliases += format!("alias {}{}='cd \"{}\"'\n", config.alias_prefix, name, path).as_str();
+        }
+        // A bookmark to `/` keeps its slash, and does not get a second one for a subdirectory
+        let (path, subdir) = match path.trim_end_matches('/') {
+            "" => ("/".to_string(), "${1-}"),
+            path => (shell_quote(path), "${1:+/$1}"),
+        };
+        // POSIX shells like dash only allow letters, digits and `_` in function names
+        if is_function_name(&function) {
+            functions += format!("{function}() {{ cd -- {path}\"{subdir}\"; }}\n").as_str();
+        } else {
+            functions += format!("alias {function}='__bmark_cd {function}'\n").as_str();
+            has_aliases = true;
+        }
+        roots += format!("        {function}) printf '%s' {path} ;;\n").as_str();
+        names.push(function);
+    }
+    if names.is_empty() {
+        return functions + extra.as_str();
+    }
+    let names = names.join(" ");
+    if has_aliases {
+        functions += r#"
+__bmark_cd() {
+    set -- "$(__bmark_root "$1")" "${2-}"
+    case "$1" in
+        */) cd -- "$1$2" ;;
+        *) cd -- "$1${2:+/$2}" ;;
+    esac
+}
+"#;
+    }
+
+    format!(
+        r#"{functions}
+__bmark_root() {{
+    case "$1" in
+{roots}    esac
+}}
+
+if [ -n "${{ZSH_VERSION:-}}" ]; then
+    __bmark_complete() {{
+        # Aliases are expanded before completion unless `complete_aliases` is set
+        if [ "${{words[1]}}" = __bmark_cd ]; then
+            [ "$CURRENT" -eq 3 ] && _path_files -W "$(__bmark_root "${{words[2]}}")" -/
+        else
+            _path_files -W "$(__bmark_root "${{words[1]}}")" -/
+        fi
+    }}
+    if (( $+functions[compdef] )); then
+        compdef __bmark_complete {names} __bmark_cd
+    fi
+elif [ -n "${{BASH_VERSION:-}}" ]; then
+    __bmark_complete() {{
+        local root cur dir
+        root="$(__bmark_root "$1")"
+        cur="${{COMP_WORDS[COMP_CWORD]}}"
+        unset COMPREPLY
+        for dir in "$root/$cur"*/; do
+            [ -d "$dir" ] && COMPREPLY[${{#COMPREPLY[@]}}]="${{dir#"$root/"}}"
+        done
+    }}
+    complete -o nospace -o filenames -F __bmark_complete {names}
+fi
+{extra}"#
+    )
+}
+
+/// Whether a name can be used as a function name in every POSIX shell.
+fn is_function_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Keep a symlink to every bookmark in the links directory when the `cdpath` option is set, and
+/// remove the symlinks otherwise.
+#[cfg(unix)]
+fn update_links(config: &Config, bookmarks: &BTreeMap<String, String>) -> BmarkResult {
+    let dir = config.get_links_dir();
+    let wanted: BTreeMap<&String, &String> = match config.cdpath {
+        true => bookmarks.iter().filter(|(name, _)| is_link_name(name)).collect(),
+        false => BTreeMap::new(),
+    };
+    if wanted.is_empty() && !dir.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("ERROR: Could not create links directory: {e}"))?;
+
+    // Only touch symlinks, anything else in the directory was put there by the user
+    for entry in fs::read_dir(&dir).into_iter().flatten().filter_map(|e| e.ok()) {
+        let link = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let target = fs::read_link(&link).ok();
+        let up_to_date = wanted.get(&name).map(PathBuf::from) == target;
+        if target.is_some() && !up_to_date {
+            let _ = fs::remove_file(&link);
+        }
     }
+    for (name, path) in wanted {
+        let link = dir.join(name);
+        if fs::symlink_metadata(&link).is_err() {
+            std::os::unix::fs::symlink(path, &link)
+                .map_err(|e| format!("ERROR: Could not create link `{}`: {e}", link.display()))?;
+        }
+    }
+    if !config.cdpath {
+        let _ = fs::remove_dir(&dir);
+    }
+    config.log(format!("updated links in `{}`", dir.display()));
+    Ok(())
+}
+
+/// Symlinks are only created on Unix, elsewhere the `cdpath` option has no effect.
+#[cfg(not(unix))]
+fn update_links(config: &Config, _bookmarks: &BTreeMap<String, String>) -> BmarkResult {
+    if config.cdpath {
+        eprintln!("WARNING: The `cdpath` option needs symlinks, which are only supported on Unix.");
+    }
+    Ok(())
+}
+
+/// The bookmarks that get aliases, links and variables. Glob bookmarks do not point to a single
+/// directory, so they are left out.
+fn alias_map(bookmarks: &Bookmarks) -> Result<BTreeMap<String, String>, String> {
+    let mut map = bookmarks.try_get_map()?;
+    map.retain(|_, path| !paths::is_glob(path));
+    Ok(map)
+}
+
+/// Write the Lua module for Neovim, and the Vim script when the `vimscript` option is set.
+fn update_editor_files(config: &Config, map: &BTreeMap<String, String>) -> BmarkResult {
+    let write = |name: &str, contents: String| {
+        let file = config.data_dir.join(name);
+        bookmarks::write_atomic(&file, &contents)?;
+        config.log(format!("wrote `{}`", file.display()));
+        Ok::<(), String>(())
+    };
+    write(LUA_FILE, editor::lua(map))?;
+    match config.vimscript {
+        true => write(VIM_FILE, editor::vimscript(map))?,
+        false => {
+            let _ = fs::remove_file(config.data_dir.join(VIM_FILE));
+        }
+    }
+    Ok(())
+}
+
+fn bmark_update(config: &Config) -> BmarkResult {
+    let bookmarks = Bookmarks::from_config(config);
+    let map = alias_map(&bookmarks)?;
+    update_links(config, &map)?;
+    let aliases = aliases(config, &map);
     let bytes = aliases.as_bytes();
     match OpenOptions::new()
         .write(true)
@@ -534,9 +1140,132 @@ fn bmark_update(config: &Config) -> BmarkResult {
     {
         Ok(mut file) => match file.write_all(bytes) {
             Ok(_) => {}
-            Err(_) => return Err(format!("ERROR: Could not write to aliases file")),
+            Err(_) => return Err("ERROR: Could not write to aliases file".to_string()),
         },
-        Err(_) => return Err(format!("ERROR: Could not open aliases file")),
+        Err(_) => return Err("ERROR: Could not open aliases file".to_string()),
+    }
+    config.log(format!("wrote `{}`", config.get_alias_file().display()));
+    update_editor_files(config, &map)?;
+
+    if config.gtk_sync {
+        let file = import::Source::Gtk
+            .default_file()
+            .ok_or("ERROR: Could not determine the GTK bookmarks file.")?;
+        export_gtk(config, &bookmarks, &file)?;
+    }
+    if config.desktop_entries {
+        let dir = export::desktop_dir().ok_or("ERROR: Could not determine the applications directory.")?;
+        export_desktop(config, &bookmarks, &dir)?;
+    }
+    Ok(())
+}
+
+fn bmark_doctor(config: &Config, format: Format) -> BmarkResult {
+    let mut records = Records::new(&["check", "status", "message"]);
+    let mut report = |check: &str, status: &str, message: String| {
+        records.push(vec![
+            Value::from(check),
+            Value::from(status),
+            Value::from(message),
+        ]);
+    };
+
+    let path = &config.config_file;
+    if !path.exists() {
+        report(
+            "config",
+            "ok",
+            format!("no config file at `{}`, using defaults", path.display()),
+        );
+    } else {
+        match FileConfig::read(path) {
+            Ok((_, warnings)) if !warnings.is_empty() => {
+                for w in warnings {
+                    report("config", "warning", w.trim_start_matches("WARNING: ").to_string());
+                }
+            }
+            Ok(_) => report("config", "ok", format!("`{}` is valid", path.display())),
+            Err(e) => report("config", "error", e),
+        }
+    }
+
+    let bookmarks = Bookmarks::from_config(config);
+    let map = match bookmarks.try_get_map() {
+        Ok(map) => {
+            report(
+                "bookmarks",
+                "ok",
+                format!("`{}` is valid", bookmarks.file.display()),
+            );
+            map
+        }
+        Err(e) => {
+            report("bookmarks", "error", e);
+            BTreeMap::new()
+        }
+    };
+
+    for problem in bookmarks.reference_problems().unwrap_or_default() {
+        report("reference", "error", problem);
+    }
+
+    for (name, path) in &map {
+        if paths::is_glob(path) {
+            if paths::glob(path).is_empty() {
+                report(
+                    "path",
+                    "warning",
+                    format!("bookmark `{name}` is a pattern that matches no directories: `{path}`"),
+                );
+            }
+            continue;
+        }
+        if !Path::new(path).is_dir() {
+            report(
+                "path",
+                "warning",
+                format!("bookmark `{name}` points to a missing directory: `{path}`"),
+            );
+        }
+        if !bookmarks::is_alias_name(name) {
+            report(
+                "alias",
+                "warning",
+                format!("bookmark `{name}` contains spaces or special characters and has no alias"),
+            );
+        }
+    }
+
+    let alias_file = config.get_alias_file();
+    match fs::read_to_string(&alias_file) {
+        Ok(current) if alias_map(&bookmarks).is_ok_and(|map| current == aliases(config, &map)) => {
+            report("aliases", "ok", format!("`{}` is up to date", alias_file.display()))
+        }
+        Ok(_) => report(
+            "aliases",
+            "warning",
+            format!("`{}` is out of date, run `bmark update`", alias_file.display()),
+        ),
+        Err(_) => report(
+            "aliases",
+            "warning",
+            format!("`{}` does not exist, run `bmark update`", alias_file.display()),
+        ),
+    }
+
+    let failed = records.rows().any(|row| row[1] == "error");
+
+    if format == Format::Human {
+        for row in records.rows() {
+            let status = row[1].as_str().unwrap_or_default().to_uppercase();
+            println!("{:<7} {}", status, row[2].as_str().unwrap_or_default());
+        }
+    } else {
+        print!("{}", records.render(format));
+    }
+
+    if failed {
+        return Err("ERROR: bmark doctor found problems.".to_string());
     }
     Ok(())
 }
@@ -544,9 +1273,18 @@ fn bmark_update(config: &Config) -> BmarkResult {
 type BmarkResult = Result<(), String>;
 
 fn main() {
+    // Hidden entry point for the completion scripts, which pass the words of the command line
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "__complete") {
+        print!("{}", complete::complete(&args[1..]));
+        return;
+    }
+
     let opts = cli::Opts::parse_args_default_or_exit();
 
-    let mut config = match Config::new(&opts) {
+    let format = opts.format.unwrap_or_default();
+
+    let config = match Config::new(&opts) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{e}");
@@ -566,15 +1304,43 @@ fn main() {
     };
 
     let res = match cmd {
+        cli::Command::Add(add_opts) if add_opts.stdin => match add_opts.name {
+            Some(_) => Err("ERROR: Names are given on stdin with --stdin, as `name<TAB>path`.".to_string()),
+            None => bmark_add_stdin(&config),
+        },
         cli::Command::Add(add_opts) => bmark_add(&config, add_opts.name),
+        cli::Command::Scan(scan_opts) => bmark_scan(&config, &scan_opts),
+        cli::Command::Import(import_opts) => bmark_import(&config, &import_opts),
+        cli::Command::Export(export_opts) => bmark_export(&config, &export_opts),
         cli::Command::Edit(_) => bmark_edit(&config),
-        cli::Command::List(_) => bmark_list(&mut config),
-        cli::Command::Open(open_opts) => bmark_open(&mut config, &open_opts),
-        cli::Command::Rm(rm_opts) => bmark_rm(&config, rm_opts.name),
+        cli::Command::List(list_opts) => {
+            bmark_list(&config, &list_opts, list_opts.format.unwrap_or(format))
+        }
+        cli::Command::Open(_) => bmark_open(&config),
+        cli::Command::Path(path_opts) => {
+            bmark_path(&config, &path_opts, path_opts.format.unwrap_or(format))
+        }
+        cli::Command::Which(which_opts) => {
+            bmark_which(&config, &which_opts, which_opts.format.unwrap_or(format))
+        }
+        cli::Command::Rm(rm_opts) => bmark_rm(&config, &rm_opts),
+        cli::Command::Rename(rename_opts) => bmark_rename(&config, &rename_opts),
+        cli::Command::Undo(_) => bmark_undo(&config),
+        cli::Command::History(history_opts) => {
+            bmark_history(&config, history_opts.format.unwrap_or(format))
+        }
+        cli::Command::Restore(restore_opts) => bmark_restore(&config, &restore_opts),
+        cli::Command::Sync(sync_opts) => bmark_sync(&config, sync_opts.command),
         cli::Command::Update(_) => bmark_update(&config),
+        cli::Command::Completions(completions_opts) => {
+            complete::script(&completions_opts.shell).map(|s| print!("{s}"))
+        }
+        cli::Command::Doctor(doctor_opts) => {
+            bmark_doctor(&config, doctor_opts.format.unwrap_or(format))
+        }
         cli::Command::Config(config_opts) => {
             if let Some(cmd) = config_opts.command {
-                bmark_config(&config, cmd)
+                bmark_config(&config, cmd, format)
             } else {
                 let msg = format!(
                     "Please supply a subcommand for `bmark config`.\n\nSubcommands:\n{}",
@@ -0,0 +1,148 @@
+/// Score how well `pattern` fuzzy-matches `text`.
+///
+/// Every character of the pattern has to appear in the text in the same order (ignoring case).
+/// Returns `None` if the pattern does not match, otherwise a score where higher is better.
+/// Consecutive matches and matches at word boundaries are rewarded.
+pub(crate) fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+
+    for p in pattern.chars().flat_map(char::to_lowercase) {
+        let i = pos + text[pos..].iter().position(|c| *c == p)?;
+        score += 1;
+        if last_match == Some(i.wrapping_sub(1)) {
+            score += 5;
+        }
+        if i == 0 || matches!(text[i - 1], '/' | '-' | '_' | '.' | ' ') {
+            score += 3;
+        }
+        last_match = Some(i);
+        pos = i + 1;
+    }
+
+    // Prefer shorter texts when everything else is equal
+    Some(score * 100 - text.len() as i64)
+}
+
+/// The Levenshtein distance between two strings.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(prev + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `word`, if any is close enough to be a likely typo.
+pub(crate) fn suggest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (word.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(word, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Whether `name` matches a shell wildcard pattern. `*` matches any characters, `?` any single
+/// character and `[...]` one of a class of characters like `[abc]`, `[a-z]` or `[!0-9]`.
+pub(crate) fn wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    wildcard_at(&pattern, &name)
+}
+
+fn wildcard_at(p: &[char], n: &[char]) -> bool {
+    match p.first() {
+        None => n.is_empty(),
+        Some('*') => (0..=n.len()).any(|i| wildcard_at(&p[1..], &n[i..])),
+        Some('?') => !n.is_empty() && wildcard_at(&p[1..], &n[1..]),
+        Some('[') if class_end(p).is_some() => {
+            let end = class_end(p).expect("checked above");
+            match n.first() {
+                Some(c) => class_matches(&p[1..end], *c) && wildcard_at(&p[end + 1..], &n[1..]),
+                None => false,
+            }
+        }
+        Some(c) => n.first() == Some(c) && wildcard_at(&p[1..], &n[1..]),
+    }
+}
+
+/// The index of the `]` closing the class that `p` starts with. A `]` right after the opening
+/// `[` or `[!` is part of the class.
+fn class_end(p: &[char]) -> Option<usize> {
+    let mut start = 1;
+    if matches!(p.get(start), Some('!' | '^')) {
+        start += 1;
+    }
+    if p.get(start) == Some(&']') {
+        start += 1;
+    }
+    Some(start + p.get(start..)?.iter().position(|c| *c == ']')?)
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_stars_and_question_marks() {
+        assert!(wildcard("*", ""));
+        assert!(wildcard("build-*", "build-1234"));
+        assert!(wildcard("*-x*", "feature-x"));
+        assert!(!wildcard("build-*", "release-1"));
+        assert!(wildcard("v?", "v1"));
+        assert!(!wildcard("v?", "v"));
+        assert!(!wildcard("v?", "v10"));
+        assert!(wildcard("exact", "exact"));
+        assert!(!wildcard("exact", "exactly"));
+    }
+
+    #[test]
+    fn wildcard_classes() {
+        assert!(wildcard("[abc]x", "bx"));
+        assert!(!wildcard("[abc]x", "dx"));
+        assert!(wildcard("v[0-9]", "v7"));
+        assert!(!wildcard("v[0-9]", "va"));
+        assert!(wildcard("v[!0-9]", "va"));
+        assert!(wildcard("v[^0-9]", "va"));
+        assert!(!wildcard("v[!0-9]", "v7"));
+        assert!(wildcard("[]a]", "]"));
+        assert!(wildcard("[!]]", "a"));
+        assert!(wildcard("[a-]", "-"));
+    }
+
+    #[test]
+    fn wildcard_unclosed_class_is_literal() {
+        assert!(wildcard("[ab", "[ab"));
+        assert!(!wildcard("[ab", "a"));
+    }
+}
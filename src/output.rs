@@ -0,0 +1,222 @@
+use serde_json::{Map, Value};
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use unicode_width::UnicodeWidthChar;
+
+/// Output format selected with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Format {
+    #[default]
+    Human,
+    Json,
+    Tsv,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "tsv" => Ok(Format::Tsv),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "unknown format `{s}`, expected one of: human, json, tsv, csv"
+            )),
+        }
+    }
+}
+
+/// A list of records with named fields, that can be rendered in the machine-readable formats.
+pub(crate) struct Records {
+    fields: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Records {
+    pub(crate) fn new(fields: &[&'static str]) -> Self {
+        Self {
+            fields: fields.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    pub(crate) fn push(&mut self, row: Vec<Value>) {
+        debug_assert_eq!(row.len(), self.fields.len());
+        self.rows.push(row);
+    }
+
+    pub(crate) fn rows(&self) -> impl Iterator<Item = &Vec<Value>> {
+        self.rows.iter()
+    }
+
+    /// Render the records. JSON is an array of objects, TSV and CSV have a header line.
+    /// The human format has no generic representation, so callers handle it themselves.
+    pub(crate) fn render(&self, format: Format) -> String {
+        match format {
+            Format::Json => {
+                let objects: Vec<Value> = self
+                    .rows
+                    .iter()
+                    .map(|row| Value::Object(self.object(row)))
+                    .collect();
+                Value::Array(objects).to_string() + "\n"
+            }
+            Format::Tsv => self.delimited(tsv_field, "\t"),
+            Format::Csv => self.delimited(csv_field, ","),
+            Format::Human => unreachable!("human output is rendered by each command"),
+        }
+    }
+
+    /// Render a single record. JSON is an object instead of an array.
+    pub(crate) fn render_single(&self, format: Format) -> String {
+        match (format, self.rows.first()) {
+            (Format::Json, Some(row)) => Value::Object(self.object(row)).to_string() + "\n",
+            _ => self.render(format),
+        }
+    }
+
+    fn object(&self, row: &[Value]) -> Map<String, Value> {
+        self.fields
+            .iter()
+            .zip(row)
+            .map(|(f, v)| (f.to_string(), v.clone()))
+            .collect()
+    }
+
+    fn delimited(&self, field: fn(&Value) -> String, sep: &str) -> String {
+        let mut out = self.fields.join(sep) + "\n";
+        for row in &self.rows {
+            let row: Vec<String> = row.iter().map(field).collect();
+            out += row.join(sep).as_str();
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn plain(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(a) => a.iter().map(plain).collect::<Vec<String>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+fn tsv_field(value: &Value) -> String {
+    plain(value)
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn csv_field(value: &Value) -> String {
+    let s = plain(value);
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s
+    }
+}
+
+/// How human-readable output is decorated.
+#[derive(Debug, Default)]
+pub(crate) struct Style {
+    /// Maximum line width, longer paths are shortened to fit
+    pub(crate) width: Option<usize>,
+    /// Colour output with ANSI escape codes
+    pub(crate) color: bool,
+    /// Abbreviate the home directory to `~`
+    pub(crate) tilde: bool,
+    /// Show bookmark tags
+    pub(crate) tags: bool,
+}
+
+impl Style {
+    /// Style for output to stdout. Width and colours are only used when stdout is a terminal, and
+    /// colours can be disabled with the `NO_COLOR` environment variable.
+    pub(crate) fn stdout() -> Self {
+        let tty = io::stdout().is_terminal();
+        Self {
+            width: if tty { terminal_width() } else { None },
+            color: tty && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+            tilde: true,
+            tags: true,
+        }
+    }
+}
+
+fn terminal_width() -> Option<usize> {
+    if let Some(Ok(cols)) = env::var("COLUMNS").ok().map(|c| c.parse()) {
+        return Some(cols);
+    }
+    terminal_size::terminal_size().map(|(w, _)| w.0 as usize)
+}
+
+/// Abbreviate a path in the home directory with `~`.
+pub(crate) fn tilde(path: &str, home: &str) -> String {
+    let home = home.trim_end_matches('/');
+    match path.strip_prefix(home) {
+        Some("") => "~".to_string(),
+        Some(rest) if rest.starts_with('/') => format!("~{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+/// Shorten a string to at most `width` columns by replacing its beginning with an ellipsis.
+pub(crate) fn ellipsise(s: &str, width: usize) -> String {
+    let total: usize = s.chars().filter_map(|c| c.width()).sum();
+    if total <= width || width < 2 {
+        return s.to_string();
+    }
+    let mut kept = vec![];
+    let mut used = 1; // The ellipsis
+    for c in s.chars().rev() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        used += w;
+        kept.push(c);
+    }
+    kept.push('…');
+    kept.into_iter().rev().collect()
+}
+
+pub(crate) fn red(s: &str) -> String {
+    format!("\x1b[31m{s}\x1b[0m")
+}
+
+pub(crate) fn dim(s: &str) -> String {
+    format!("\x1b[2m{s}\x1b[0m")
+}
+
+/// Ask the user a yes or no question on the terminal, where anything but yes is no. Without a
+/// terminal to ask on, this fails with a hint to use `--yes` to `action` without asking.
+pub(crate) fn confirm(question: &str, action: &str) -> Result<bool, String> {
+    match ask(question).as_deref() {
+        Some("y" | "Y" | "yes") => Ok(true),
+        Some(_) => Ok(false),
+        None => Err(format!("ERROR: Cannot ask for confirmation, use --yes to {action} without asking.")),
+    }
+}
+
+/// Ask the user a question on the terminal. Returns `None` if stdin is not a terminal, so that
+/// scripts never hang waiting for an answer.
+pub(crate) fn ask(question: &str) -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    eprint!("{question}");
+    io::stderr().flush().ok()?;
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim_end_matches('\n').to_string()),
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{matching, output};
+
+/// The name of this machine, used to select its `[path_maps]`.
+pub(crate) fn hostname() -> String {
+    if let Ok(name) = fs::read_to_string("/etc/hostname") {
+        if !name.trim().is_empty() {
+            return name.trim().to_string();
+        }
+    }
+    Command::new("hostname")
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The rest of `path` after `prefix`, if `path` is `prefix` or a path below it.
+fn strip_dir_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix)? {
+        "" => Some(""),
+        rest if rest.starts_with('/') || prefix.is_empty() => Some(rest),
+        _ => None,
+    }
+}
+
+/// Turn a stored path into a path on this machine. The longest matching prefix in `maps` is
+/// rewritten first, and then a leading `~` and `$VAR` or `${VAR}` environment variables are
+/// expanded. Unset variables are left as they are.
+pub(crate) fn expand(stored: &str, maps: &[(String, String)]) -> String {
+    let rewritten = maps
+        .iter()
+        .filter_map(|(from, to)| Some((from.len(), to, strip_dir_prefix(stored, from)?)))
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, to, rest)| format!("{}{rest}", to.trim_end_matches('/')));
+    let path = rewritten.as_deref().unwrap_or(stored);
+
+    let home = env::var("HOME").unwrap_or_default();
+    let path = match path.strip_prefix('~') {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            format!("{}{rest}", home.trim_end_matches('/'))
+        }
+        _ => path.to_string(),
+    };
+    expand_vars(&path)
+}
+
+fn expand_vars(path: &str) -> String {
+    let mut out = String::new();
+    let mut rest = path;
+    while let Some(i) = rest.find('$') {
+        out += &rest[..i];
+        let after = &rest[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match env::var(name) {
+            Ok(value) if !name.is_empty() => out += &value,
+            _ => out += &rest[i..i + 1 + len],
+        }
+        rest = &after[len..];
+    }
+    out + rest
+}
+
+/// The portable form of a path on this machine, to be stored in the bookmarks file. This undoes
+/// the longest matching rewrite in `maps`, or abbreviates the home directory to `~`.
+pub(crate) fn portable(path: &str, maps: &[(String, String)]) -> String {
+    let unmapped = maps
+        .iter()
+        .filter_map(|(from, to)| {
+            let to = expand(to, &[]);
+            Some((to.len(), from, strip_dir_prefix(path, &to)?))
+        })
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, from, rest)| format!("{}{rest}", from.trim_end_matches('/')));
+    if let Some(unmapped) = unmapped {
+        return unmapped;
+    }
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && home != "/" => output::tilde(path, &home),
+        _ => path.to_string(),
+    }
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Whether a bookmarked path is a glob pattern standing for all directories that match it. A
+/// directory that exists with wildcard characters in its name is not a pattern.
+pub(crate) fn is_glob(path: &str) -> bool {
+    has_wildcards(path) && !Path::new(path).exists()
+}
+
+/// The directories matching a glob pattern, sorted, together with the part of their path that
+/// the wildcards matched. Hidden directories only match components starting with a `.`.
+pub(crate) fn glob(pattern: &str) -> Vec<(String, PathBuf)> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let first = components
+        .iter()
+        .position(|c| has_wildcards(c))
+        .unwrap_or(components.len());
+    let base = match components[..first].join("/") {
+        base if !base.is_empty() => base,
+        _ if pattern.starts_with('/') => "/".to_string(),
+        _ => ".".to_string(),
+    };
+
+    let mut found = vec![(String::new(), PathBuf::from(base))];
+    for part in components[first..].iter().filter(|c| !c.is_empty()) {
+        let join = |matched: &str, name: &str| match matched {
+            "" => name.to_string(),
+            _ => format!("{matched}/{name}"),
+        };
+        found = found
+            .into_iter()
+            .flat_map(|(matched, dir)| {
+                if !has_wildcards(part) {
+                    let path = dir.join(part);
+                    return match path.is_dir() {
+                        true => vec![(join(&matched, part), path)],
+                        false => vec![],
+                    };
+                }
+                let Ok(entries) = fs::read_dir(&dir) else {
+                    return vec![];
+                };
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter_map(|e| {
+                        let name = e.file_name().to_string_lossy().to_string();
+                        let hidden = name.starts_with('.') && !part.starts_with('.');
+                        let path = e.path();
+                        (!hidden && path.is_dir() && matching::wildcard(part, &name))
+                            .then(|| (join(&matched, &name), path))
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+    found.sort();
+    found
+}
+
+/// The names in the `${name}` placeholders of a stored path.
+pub(crate) fn references(stored: &str) -> Vec<&str> {
+    let mut refs = vec![];
+    let mut rest = stored;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                refs.push(&after[..end]);
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    refs
+}
+
+/// Resolve the stored paths of all bookmarks. A path can start from another bookmark with
+/// `${name}`, which is resolved recursively and takes precedence over an environment variable of
+/// the same name. Bookmarks with a reference that cannot be resolved keep their path as written,
+/// and the problems are returned alongside the paths.
+pub(crate) fn resolve(
+    stored: &BTreeMap<String, String>,
+    maps: &[(String, String)],
+) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut resolved = BTreeMap::new();
+    let mut problems = vec![];
+    for (name, path) in stored {
+        let path = match resolve_one(name, stored, maps, &mut vec![]) {
+            Ok(path) => path,
+            Err(e) => {
+                if !problems.contains(&e) {
+                    problems.push(e);
+                }
+                expand(path, maps)
+            }
+        };
+        resolved.insert(name.clone(), path);
+    }
+    (resolved, problems)
+}
+
+fn resolve_one(
+    name: &str,
+    stored: &BTreeMap<String, String>,
+    maps: &[(String, String)],
+    chain: &mut Vec<String>,
+) -> Result<String, String> {
+    if let Some(i) = chain.iter().position(|n| n == name) {
+        // Start the cycle at its first name, so that it is reported the same from every bookmark
+        let mut cycle = chain[i..].to_vec();
+        let first = (0..cycle.len()).min_by_key(|j| &cycle[*j]).unwrap_or(0);
+        cycle.rotate_left(first);
+        cycle.push(cycle[0].clone());
+        return Err(format!(
+            "bookmarks reference each other in a cycle: {}",
+            cycle.join(" -> ")
+        ));
+    }
+    let path = &stored[name];
+    let refs = references(path);
+    if let Some(r) = refs.iter().find(|r| !stored.contains_key(**r) && env::var(r).is_err()) {
+        return Err(format!("bookmark `{name}` references unknown bookmark `{r}`"));
+    }
+    let bookmark_refs: Vec<&str> = refs.into_iter().filter(|r| stored.contains_key(*r)).collect();
+    if bookmark_refs.is_empty() {
+        return Ok(expand(path, maps));
+    }
+
+    chain.push(name.to_string());
+    let mut path = path.clone();
+    for r in bookmark_refs {
+        let target = resolve_one(r, stored, maps, chain)?;
+        path = path.replace(&format!("${{{r}}}"), &target);
+    }
+    chain.pop();
+    // The referenced paths are already paths on this machine, so they must not be rewritten again
+    Ok(expand(&path, &[]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn strip_dir_prefix_only_matches_whole_components() {
+        assert_eq!(strip_dir_prefix("/home/me/src", "/home/me"), Some("/src"));
+        assert_eq!(strip_dir_prefix("/home/me/src", "/home/me/"), Some("/src"));
+        assert_eq!(strip_dir_prefix("/home/me", "/home/me"), Some(""));
+        assert_eq!(strip_dir_prefix("/home/meh", "/home/me"), None);
+        assert_eq!(strip_dir_prefix("/srv", "/"), Some("/srv"));
+    }
+
+    #[test]
+    fn expand_home_variables_and_maps() {
+        let home = env::var("HOME").unwrap();
+        let home = home.trim_end_matches('/');
+        assert_eq!(expand("~", &[]), home);
+        assert_eq!(expand("~/src", &[]), format!("{home}/src"));
+        assert_eq!(expand("~other/src", &[]), "~other/src");
+        assert_eq!(expand("$HOME/src", &[]), format!("{home}/src"));
+        assert_eq!(expand("${HOME}src", &[]), format!("{home}src"));
+        assert_eq!(
+            expand("/a/$BMARK_TEST_UNSET/b", &[]),
+            "/a/$BMARK_TEST_UNSET/b"
+        );
+        assert_eq!(
+            expand("/a/${BMARK_TEST_UNSET}/b", &[]),
+            "/a/${BMARK_TEST_UNSET}/b"
+        );
+        assert_eq!(expand("/a/${unclosed", &[]), "/a/${unclosed");
+        assert_eq!(expand("/price/$", &[]), "/price/$");
+
+        let maps = maps(&[
+            ("~/src", "/work/src/"),
+            ("~/src/web", "/srv/web"),
+            ("/mnt", "/media"),
+        ]);
+        assert_eq!(expand("~/src/app", &maps), "/work/src/app");
+        assert_eq!(expand("~/src/web/site", &maps), "/srv/web/site");
+        assert_eq!(expand("~/srcs", &maps), format!("{home}/srcs"));
+        assert_eq!(expand("/mnt", &maps), "/media");
+    }
+
+    #[test]
+    fn portable_undoes_maps_and_abbreviates_home() {
+        let home = env::var("HOME").unwrap();
+        let home = home.trim_end_matches('/');
+        let maps = maps(&[
+            ("~/src", "/work/src"),
+            ("~/src/web", "/srv/web"),
+            ("/mnt", "$HOME/mnt"),
+        ]);
+        assert_eq!(portable("/work/src/app", &maps), "~/src/app");
+        assert_eq!(portable("/srv/web/site", &maps), "~/src/web/site");
+        assert_eq!(portable(&format!("{home}/mnt/disk"), &maps), "/mnt/disk");
+        assert_eq!(portable(&format!("{home}/notes"), &maps), "~/notes");
+        assert_eq!(portable("/work/srcs", &maps), "/work/srcs");
+        for path in ["~/src/app", "~/src/web/site", "/mnt/disk", "~/notes"] {
+            assert_eq!(portable(&expand(path, &maps), &maps), path);
+        }
+    }
+
+    fn stored(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, path)| (name.to_string(), path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn resolve_references_recursively() {
+        let stored = stored(&[
+            ("proj", "/src/proj"),
+            ("docs", "${proj}/docs"),
+            ("api", "${docs}/api"),
+            ("plain", "/plain"),
+        ]);
+        let (resolved, problems) = resolve(&stored, &[]);
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(resolved["docs"], "/src/proj/docs");
+        assert_eq!(resolved["api"], "/src/proj/docs/api");
+        assert_eq!(resolved["plain"], "/plain");
+    }
+
+    #[test]
+    fn resolve_maps_referenced_paths_once() {
+        let stored = stored(&[("proj", "/old/proj"), ("docs", "${proj}/docs")]);
+        let (resolved, _) = resolve(&stored, &maps(&[("/old", "/new"), ("/new", "/newer")]));
+        assert_eq!(resolved["proj"], "/new/proj");
+        assert_eq!(resolved["docs"], "/new/proj/docs");
+    }
+
+    #[test]
+    fn resolve_prefers_bookmarks_over_variables() {
+        let home = env::var("HOME").unwrap();
+        let stored = stored(&[
+            ("HOME", "/bookmarked"),
+            ("a", "${HOME}/a"),
+            ("b", "$HOME/b"),
+        ]);
+        let (resolved, problems) = resolve(&stored, &[]);
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(resolved["a"], "/bookmarked/a");
+        assert_eq!(resolved["b"], format!("{}/b", home.trim_end_matches('/')));
+    }
+
+    #[test]
+    fn resolve_reports_unknown_references() {
+        let stored = stored(&[("docs", "${nope}/docs"), ("sub", "${docs}/sub")]);
+        let (resolved, problems) = resolve(&stored, &[]);
+        assert_eq!(
+            problems,
+            ["bookmark `docs` references unknown bookmark `nope`"]
+        );
+        assert_eq!(resolved["docs"], "${nope}/docs");
+        assert_eq!(resolved["sub"], "${docs}/sub");
+    }
+
+    #[test]
+    fn resolve_reports_cycles_once() {
+        let cycle = stored(&[
+            ("b", "${c}/b"),
+            ("c", "${a}/c"),
+            ("a", "${b}/a"),
+            ("d", "${a}/d"),
+        ]);
+        let (resolved, problems) = resolve(&cycle, &[]);
+        assert_eq!(
+            problems,
+            ["bookmarks reference each other in a cycle: a -> b -> c -> a"]
+        );
+        assert_eq!(resolved["d"], "${a}/d");
+
+        let (_, problems) = resolve(&stored(&[("me", "${me}/x")]), &[]);
+        assert_eq!(
+            problems,
+            ["bookmarks reference each other in a cycle: me -> me"]
+        );
+    }
+
+    #[test]
+    fn glob_matches_directories() {
+        let root = std::env::temp_dir().join(format!("bmark-test-glob-{}", std::process::id()));
+        for dir in [
+            "wt/feature-x/src",
+            "wt/feature-y",
+            "wt/.hidden",
+            "build/b-1/out",
+            "build/b-2",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("wt/file"), "").unwrap();
+        let root_str = root.display().to_string();
+        let matches = |pattern: &str| -> Vec<String> {
+            glob(&format!("{root_str}/{pattern}"))
+                .into_iter()
+                .map(|(matched, path)| {
+                    assert!(
+                        path.is_dir() && path.ends_with(&matched),
+                        "{}",
+                        path.display()
+                    );
+                    matched
+                })
+                .collect()
+        };
+
+        assert_eq!(matches("wt/*"), ["feature-x", "feature-y"]);
+        assert_eq!(matches("wt/.*"), [".hidden"]);
+        assert_eq!(matches("wt/feature-?/src"), ["feature-x/src"]);
+        assert_eq!(matches("*/*-[12]"), ["build/b-1", "build/b-2"]);
+        assert_eq!(matches("*/*/out"), ["build/b-1/out"]);
+        assert!(matches("wt/none-*").is_empty());
+
+        assert!(is_glob(&format!("{root_str}/wt/*")));
+        assert!(!is_glob(&format!("{root_str}/wt")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What makes a directory a project root.
+pub(crate) struct Markers {
+    /// Directories containing a `.git` directory or file
+    pub(crate) git: bool,
+    /// Directories containing any of these files
+    pub(crate) files: Vec<String>,
+}
+
+impl Markers {
+    fn matches(&self, dir: &Path) -> bool {
+        (self.git && dir.join(".git").exists()) || self.files.iter().any(|f| dir.join(f).exists())
+    }
+}
+
+/// Find project roots in `root` and up to `depth` directories below it. Project roots are not
+/// searched for nested projects, and hidden directories and symlinks are skipped.
+pub(crate) fn find_projects(root: &Path, depth: usize, markers: &Markers) -> Vec<PathBuf> {
+    let mut found = vec![];
+    walk(root, depth, markers, &mut found);
+    found.sort();
+    found
+}
+
+fn walk(dir: &Path, depth: usize, markers: &Markers, found: &mut Vec<PathBuf>) {
+    if markers.matches(dir) {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if is_dir && !hidden {
+            walk(&entry.path(), depth - 1, markers, found);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use toml_edit::Document;
+
+use crate::bookmarks::{self, Entry};
+use crate::config::BOOKMARKS_FILE;
+use crate::history;
+
+static GITIGNORE: &str = "# Files that are specific to this machine
+aliases.sh
+bmark.lua
+bmark.vim
+stats.toml
+history/
+links/
+gtk-exported.txt
+desktop-exported.txt
+.*.tmp
+.bookmarks.edit.toml
+.bookmarks.lock
+";
+
+/// Run git in the data directory and return its output.
+fn git(data_dir: &Path, args: &[&str]) -> Result<String, String> {
+    let mut cmd = Command::new("git");
+    cmd.current_dir(data_dir);
+
+    // Commits should not fail on machines where git has not been set up
+    if !args.contains(&"config") && git(data_dir, &["config", "user.email"]).is_err() {
+        cmd.args(["-c", "user.name=bmark", "-c", "user.email=bmark@localhost"]);
+    }
+
+    let output = cmd
+        .args(args)
+        .output()
+        .map_err(|e| format!("ERROR: Could not run git: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ERROR: `git {}` failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+pub(crate) fn is_enabled(data_dir: &Path) -> bool {
+    data_dir.join(".git").exists()
+}
+
+/// Commit all changes in the data directory, if syncing is enabled and anything changed.
+pub(crate) fn commit(data_dir: &Path, message: &str) -> Result<(), String> {
+    if !is_enabled(data_dir) {
+        return Ok(());
+    }
+    git(data_dir, &["add", "-A"])?;
+    if git(data_dir, &["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(());
+    }
+    git(data_dir, &["commit", "-q", "-m", message])?;
+    Ok(())
+}
+
+/// Turn the data directory into a git repository syncing with `remote`, and sync with it.
+pub(crate) fn init(data_dir: &Path, remote: &str) -> Result<String, String> {
+    if !is_enabled(data_dir) {
+        git(data_dir, &["init", "-q", "-b", "main"])?;
+    }
+    let gitignore = data_dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(&gitignore, GITIGNORE)
+            .map_err(|e| format!("ERROR: Could not write `{}`: {e}", gitignore.display()))?;
+    }
+    if git(data_dir, &["remote", "get-url", "origin"]).is_ok() {
+        git(data_dir, &["remote", "set-url", "origin", remote])?;
+    } else {
+        git(data_dir, &["remote", "add", "origin", remote])?;
+    }
+    commit(data_dir, "bmark: start syncing bookmarks")?;
+    sync(data_dir)
+}
+
+/// Commit local changes, merge the bookmarks from the remote and push the result.
+/// Returns a summary of what changed locally.
+pub(crate) fn sync(data_dir: &Path) -> Result<String, String> {
+    if !is_enabled(data_dir) {
+        return Err("ERROR: Syncing is not set up, run `bmark sync init <remote>` first.".to_string());
+    }
+    commit(data_dir, "bmark: local changes")?;
+
+    let branch = git(data_dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let remote_branch = format!("origin/{branch}");
+    git(data_dir, &["fetch", "-q", "origin"])?;
+
+    let mut summary = String::new();
+    let remote_exists = git(data_dir, &["rev-parse", "--verify", "-q", &remote_branch]).is_ok();
+    let up_to_date = !remote_exists
+        || git(data_dir, &["merge-base", "--is-ancestor", &remote_branch, "HEAD"]).is_ok();
+
+    if !up_to_date {
+        let show = |rev: &str| git(data_dir, &["show", &format!("{rev}:{BOOKMARKS_FILE}")]);
+        let base = match git(data_dir, &["merge-base", "HEAD", &remote_branch]) {
+            Ok(base) => show(&base).unwrap_or_default(),
+            Err(_) => String::new(),
+        };
+        let file = data_dir.join(BOOKMARKS_FILE);
+        let ours = fs::read_to_string(&file).unwrap_or_default();
+        let theirs = show(&remote_branch).unwrap_or_default();
+
+        let merged = merge(&base, &ours, &theirs)?;
+
+        git(
+            data_dir,
+            &["merge", "-q", "-s", "ours", "--no-commit", "--allow-unrelated-histories", &remote_branch],
+        )?;
+        if merged != ours {
+            history::save(&file, &ours)?;
+            bookmarks::write_atomic(&file, &merged)?;
+        }
+        git(data_dir, &["add", BOOKMARKS_FILE])?;
+        git(data_dir, &["commit", "-q", "-m", &format!("bmark: merge {remote_branch}")])?;
+
+        let changes = history::changes(&ours, &merged);
+        if !changes.is_empty() {
+            summary += format!("Pulled: {}\n", changes.join(" ")).as_str();
+        }
+    }
+
+    git(data_dir, &["push", "-q", "-u", "origin", &branch])?;
+    summary += "Bookmarks are in sync.\n";
+    Ok(summary)
+}
+
+/// Merge two versions of the bookmarks file, starting from `ours` to keep its formatting.
+/// Bookmarks are merged one by one: a bookmark that changed on only one side since `base` takes
+/// that change, and bookmarks that were changed on one side and deleted on the other are kept.
+/// It is only a conflict when both sides point the same name to different places, when only the
+/// tags differ the tags added and removed on either side are combined.
+pub(crate) fn merge(base: &str, ours: &str, theirs: &str) -> Result<String, String> {
+    let parse = |raw: &str, which: &str| -> Result<BTreeMap<String, Entry>, String> {
+        toml::from_str(raw).map_err(|e| format!("{e}\nERROR: Could not parse {which} bookmarks."))
+    };
+    let base_map = parse(base, "common")?;
+    let ours_map = parse(ours, "local")?;
+    let theirs_map = parse(theirs, "remote")?;
+
+    let mut doc: Document = ours.parse().map_err(|e| format!("{e}"))?;
+    let theirs_doc: Document = theirs.parse().map_err(|e| format!("{e}"))?;
+
+    let names: BTreeSet<&String> = base_map
+        .keys()
+        .chain(ours_map.keys())
+        .chain(theirs_map.keys())
+        .collect();
+
+    let mut conflicts = vec![];
+    for name in names {
+        let (b, o, t) = (base_map.get(name), ours_map.get(name), theirs_map.get(name));
+        if o == t || t == b {
+            continue;
+        }
+        if o == b || o.is_none() {
+            match theirs_doc.get(name) {
+                Some(item) => doc[name.as_str()] = item.clone(),
+                None => {
+                    doc.remove(name);
+                }
+            }
+            continue;
+        }
+        if let (Some(o), Some(t)) = (o, t) {
+            if o.path() != t.path() {
+                conflicts.push(format!(
+                    "    {name}: `{}` (local) vs `{}` (remote)",
+                    o.path(),
+                    t.path()
+                ));
+                continue;
+            }
+            let base_tags = b.map(Entry::tags).unwrap_or_default();
+            let mut tags: Vec<&String> = vec![];
+            for tag in o.tags().iter().chain(t.tags()) {
+                let both = o.tags().contains(tag) && t.tags().contains(tag);
+                if (both || !base_tags.contains(tag)) && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            if tags.iter().copied().ne(o.tags()) {
+                set_tags(&mut doc, name, o.path(), &tags);
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(format!(
+            "ERROR: Bookmarks point to different places locally and remotely:\n{}\nRename or change the local bookmarks and sync again.",
+            conflicts.join("\n")
+        ));
+    }
+    Ok(doc.to_string())
+}
+
+/// Replace the tags of a bookmark, turning it into a table or back as needed.
+fn set_tags(doc: &mut Document, name: &str, path: &str, tags: &[&String]) {
+    let array: toml_edit::Array = tags.iter().map(|t| t.as_str()).collect();
+    match doc.get_mut(name).and_then(|item| item.as_table_like_mut()) {
+        Some(table) if tags.is_empty() => {
+            table.remove("tags");
+        }
+        Some(table) => {
+            table.insert("tags", toml_edit::value(array));
+        }
+        None if tags.is_empty() => doc[name] = toml_edit::value(path),
+        None => {
+            let mut table = toml_edit::InlineTable::new();
+            table.insert("path", path.into());
+            table.insert("tags", array.into());
+            doc[name] = toml_edit::value(table);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "alpha = \"~/src/alpha\"\nbeta = \"~/src/beta\"\ngamma = \"~/src/gamma\"\n";
+
+    #[test]
+    fn merge_takes_changes_from_both_sides() {
+        let ours =
+            "# mine\nalpha = \"~/src/alpha2\"\nbeta = \"~/src/beta\"\ngamma = \"~/src/gamma\"\n";
+        let theirs = "alpha = \"~/src/alpha\"\ngamma = \"~/src/gamma\"\ndelta = \"~/src/delta\"\n";
+        assert_eq!(
+            merge(BASE, ours, theirs).unwrap(),
+            "# mine\nalpha = \"~/src/alpha2\"\ngamma = \"~/src/gamma\"\ndelta = \"~/src/delta\"\n"
+        );
+    }
+
+    #[test]
+    fn merge_keeps_bookmarks_changed_on_one_side_and_deleted_on_the_other() {
+        let ours = "alpha = \"~/src/alpha2\"\nbeta = \"~/src/beta\"\n";
+        let theirs = "alpha = \"~/src/alpha\"\nbeta = \"~/src/beta2\"\n";
+        let merged = merge(BASE, ours, theirs).unwrap();
+        assert_eq!(merged, "alpha = \"~/src/alpha2\"\nbeta = \"~/src/beta2\"\n");
+
+        let merged = merge(
+            BASE,
+            "alpha = \"~/src/alpha\"\n",
+            "gamma = \"~/src/gamma2\"\n",
+        )
+        .unwrap();
+        assert_eq!(merged, "gamma = \"~/src/gamma2\"\n");
+    }
+
+    #[test]
+    fn merge_accepts_the_same_change_on_both_sides() {
+        let both =
+            "alpha = { path = \"~/src/alpha\", tags = [\"work\"] }\nepsilon = \"~/src/epsilon\"\n";
+        assert_eq!(merge(BASE, both, both).unwrap(), both);
+    }
+
+    #[test]
+    fn merge_reports_conflicting_paths() {
+        let ours = "alpha = \"~/src/one\"\nbeta = \"~/src/beta\"\nnew = \"~/a\"\n";
+        let theirs = "alpha = \"~/src/two\"\nbeta = \"~/src/beta\"\nnew = \"~/b\"\n";
+        let err = merge(BASE, ours, theirs).unwrap_err();
+        assert!(
+            err.contains("alpha: `~/src/one` (local) vs `~/src/two` (remote)"),
+            "{err}"
+        );
+        assert!(
+            err.contains("new: `~/a` (local) vs `~/b` (remote)"),
+            "{err}"
+        );
+        assert!(!err.contains("beta"), "{err}");
+    }
+
+    #[test]
+    fn merge_combines_tags_when_the_paths_agree() {
+        let base = "alpha = { path = \"~/a\", tags = [\"old\", \"kept\"] }\nbeta = \"~/b\"\n";
+        let ours = "alpha = { path = \"~/a\", tags = [\"kept\", \"mine\"] }\nbeta = { path = \"~/b\", tags = [\"x\"] }\n";
+        let theirs = "alpha = { path = \"~/a\", tags = [\"old\", \"kept\", \"theirs\"] }\nbeta = { path = \"~/b\", tags = [\"y\"] }\n";
+        assert_eq!(
+            merge(base, ours, theirs).unwrap(),
+            "alpha = { path = \"~/a\", tags = [\"kept\", \"mine\", \"theirs\"] }\nbeta = { path = \"~/b\", tags = [\"x\", \"y\"] }\n"
+        );
+
+        let ours = "alpha = \"~/a\"\n";
+        let theirs = "alpha = { path = \"~/a\", tags = [\"new\"] }\n";
+        assert_eq!(
+            merge("", ours, theirs).unwrap(),
+            "alpha = { path = \"~/a\", tags = [\"new\"] }\n"
+        );
+    }
+
+    #[test]
+    fn merge_rejects_invalid_files() {
+        let err = merge(BASE, BASE, "alpha = [").unwrap_err();
+        assert!(err.contains("Could not parse remote bookmarks"), "{err}");
+    }
+}
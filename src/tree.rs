@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+use crate::bookmarks::Bookmark;
+use crate::output::{self, Style};
+
+/// A directory in the bookmark tree, with the names of the bookmarks pointing to it.
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    bookmarks: Vec<(String, bool)>,
+}
+
+impl Node {
+    fn insert(&mut self, components: &[String], name: &str, exists: bool) {
+        match components.split_first() {
+            Some((first, rest)) => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, name, exists),
+            None => self.bookmarks.push((name.to_string(), exists)),
+        }
+    }
+}
+
+/// Render bookmarks as a tree of the directories they point to. Directories without bookmarks
+/// that only lead to a single subdirectory are collapsed into one line, and the bookmark named
+/// `current` is marked with a `*`.
+pub(crate) fn render(entries: &[Bookmark], current: Option<&str>, style: &Style) -> String {
+    let home = match style.tilde {
+        true => env::var("HOME").ok().filter(|h| !h.is_empty()),
+        false => None,
+    };
+
+    let mut root = Node::default();
+    for b in entries {
+        let path = match &home {
+            Some(home) => output::tilde(&b.path, home),
+            None => b.path.clone(),
+        };
+        let components: Vec<String> = Path::new(&path)
+            .components()
+            .filter_map(|c| match c {
+                Component::RootDir => Some("/".to_string()),
+                Component::CurDir => None,
+                c => Some(c.as_os_str().to_string_lossy().to_string()),
+            })
+            .collect();
+        root.insert(&components, &b.name, b.exists());
+    }
+
+    let mut out = String::new();
+    for (label, node) in &root.children {
+        let (label, node) = collapse(label, node);
+        out += line(&label, node, current, style).as_str();
+        render_children(node, "", current, style, &mut out);
+    }
+    out
+}
+
+fn collapse<'a>(label: &str, mut node: &'a Node) -> (String, &'a Node) {
+    let mut label = PathBuf::from(label);
+    while node.bookmarks.is_empty() && node.children.len() == 1 {
+        let (child_label, child) = node.children.iter().next().unwrap();
+        label.push(child_label);
+        node = child;
+    }
+    (label.display().to_string(), node)
+}
+
+fn render_children(node: &Node, prefix: &str, current: Option<&str>, style: &Style, out: &mut String) {
+    let count = node.children.len();
+    for (i, (label, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let (label, child) = collapse(label, child);
+        *out += prefix;
+        *out += if last { "└── " } else { "├── " };
+        *out += line(&label, child, current, style).as_str();
+        let prefix = prefix.to_string() + if last { "    " } else { "│   " };
+        render_children(child, &prefix, current, style, out);
+    }
+}
+
+fn line(label: &str, node: &Node, current: Option<&str>, style: &Style) -> String {
+    let mut line = label.to_string();
+    if style.color && node.bookmarks.iter().any(|(_, exists)| !exists) {
+        line = output::red(&line);
+    }
+    if !node.bookmarks.is_empty() {
+        let names: Vec<&str> = node.bookmarks.iter().map(|(n, _)| n.as_str()).collect();
+        line += format!(" ({})", names.join(", ")).as_str();
+    }
+    if current.is_some_and(|c| node.bookmarks.iter().any(|(n, _)| n == c)) {
+        line += " *";
+    }
+    line + "\n"
+}